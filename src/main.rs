@@ -1,14 +1,32 @@
 //#![feature(test)]
 
 extern crate time;
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
 //extern crate test;
 
-use std::collections::HashMap;
+use std::borrow::{BorrowMut, Cow};
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+#[cfg(not(feature = "btree_map"))]
+use std::collections::hash_map::Entry;
+#[cfg(feature = "btree_map")]
+use std::collections::BTreeMap;
+#[cfg(feature = "btree_map")]
+use std::collections::btree_map::Entry;
+use std::convert::TryInto;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::mem;
+#[cfg(not(test))]
 use std::env::args;
 use std::fs::File;
-use std::io::prelude::*;
 use std::ops::Index;
+use std::str;
+use std::sync::Arc;
 use JsonValue::*;
 use ErrorCode::*;
 
@@ -17,31 +35,86 @@ use ErrorCode::*;
 /// represented as a Vec of JSON values, an
 /// object is a map from string keys to JSON values
 /// and numbers are stored as f64 for simplicity.
-#[derive(Debug, Clone, PartialEq)]
+/// Integers too large for an i64 but within u64
+/// range are kept exactly in `U64`. In raw number
+/// mode numbers keep their literal text in `RawNumber`,
+/// in decimal mode they are exact `Dec` decimals.
+/// `intern_strings` turns strings into `SharedStr`,
+/// which compares equal to a `Str` with the same text.
+#[derive(Debug, Clone)]
 pub enum JsonValue {
     Null,
     Bool(bool),
     Num(f64),
+    U64(u64),
+    RawNumber(String),
+    Dec(Decimal),
     Str(String),
+    SharedStr(Arc<str>),
     Array(Vec<JsonValue>),
-    Object(HashMap<String, JsonValue>)
+    Object(Map)
 }
 
+/// Map type behind `Object`. A `HashMap` by default, or a
+/// `BTreeMap` with sorted keys when the `btree_map` feature
+/// is enabled. Keys are `Arc<str>` so that `intern_strings`
+/// can share equal keys between objects.
+#[cfg(not(feature = "btree_map"))]
+pub type Map = HashMap<Arc<str>, JsonValue>;
+#[cfg(feature = "btree_map")]
+pub type Map = BTreeMap<Arc<str>, JsonValue>;
+
+/// Shared `null` returned by accessors that must not panic.
+pub static NULL: JsonValue = Null;
+
 impl JsonValue {
     pub fn find(&self, idx: &str) -> Option<&JsonValue> {
-	match self {
-	    &Object(ref map) => map.get(idx),
+	match *self {
+	    Object(ref map) => map.get(idx),
 	    _ => None
 	}
     }
+
+    /// Like indexing with `&str`, but returns `NULL` instead of
+    /// panicking, so lookups can be chained through missing keys.
+    pub fn get_or_null(&self, idx: &str) -> &JsonValue {
+        self.find(idx).unwrap_or(&NULL)
+    }
+
+    /// The object member for `key`, or `default` if there is
+    /// none or the value isn't an object.
+    pub fn get_or<'a>(&'a self, key: &str, default: &'a JsonValue) -> &'a JsonValue {
+        self.find(key).unwrap_or(default)
+    }
     
     pub fn into_string(self) -> Option<String> {
         match self {
             JsonValue::Str(s) => Some(s),
+            SharedStr(s) => Some(s.to_string()),
             _ => None
         }
     }
 
+    /// Returns the text of a string value.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Str(ref s) => Some(s),
+            SharedStr(ref s) => Some(s),
+            _ => None
+        }
+    }
+
+    /// Replaces string values with shared `SharedStr`s and makes
+    /// equal object keys share their `Arc<str>`, so equal strings
+    /// anywhere in the tree use a single allocation. Equality
+    /// stays the same and so does the serialized document,
+    /// though objects are rebuilt, so a `HashMap` may list
+    /// their members in another order.
+    pub fn intern_strings(&mut self) {
+        let mut table = HashSet::new();
+        intern_into(self, &mut table);
+    }
+
     pub fn get_bool(self) -> Option<bool> {
         match self {
             Bool(b) => Some(b),
@@ -56,6 +129,137 @@ impl JsonValue {
         }
     }
 
+    /// Returns the value as a u64 if it is a non-negative
+    /// integer that fits.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            U64(n) => Some(n),
+            Num(n) if n.fract() == 0.0 && n >= 0.0 && n < u64::MAX as f64 => Some(n as u64),
+            RawNumber(ref s) => s.parse().ok().or_else(|| Num(s.parse().ok()?).as_u64()),
+            Dec(ref d) => d.to_string().parse().ok(),
+            _ => None
+        }
+    }
+
+    /// Returns the value as an i64 only if that is exact:
+    /// `2.0` gives `Some(2)`, while `2.5` or `1e30` give `None`.
+    pub fn as_i64_checked(&self) -> Option<i64> {
+        match *self {
+            U64(n) if n <= i64::MAX as u64 => Some(n as i64),
+            Num(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => Some(n as i64),
+            RawNumber(ref s) => s.parse().ok().or_else(|| Num(s.parse().ok()?).as_i64_checked()),
+            Dec(ref d) => d.to_string().parse().ok(),
+            _ => None
+        }
+    }
+
+    /// Returns any kind of number as an f64, possibly
+    /// losing precision.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Num(n) => Some(n),
+            U64(n) => Some(n as f64),
+            RawNumber(ref s) => s.parse().ok(),
+            Dec(ref d) => Some(d.to_f64()),
+            _ => None
+        }
+    }
+
+    /// Leniently converts the value to a number: numbers as
+    /// they are, strings holding a finite decimal number after
+    /// trimming whitespace (`"42"` is 42.0), and `true`/`false`
+    /// as 1.0/0.0. Everything else gives `None`.
+    pub fn coerce_to_number(&self) -> Option<f64> {
+        match *self {
+            Bool(b) => Some(if b { 1.0 } else { 0.0 }),
+            Str(_) | SharedStr(_) => {
+                let n: f64 = self.as_str().unwrap().trim().parse().ok()?;
+                if n.is_finite() { Some(n) } else { None }
+            },
+            _ => self.as_f64()
+        }
+    }
+
+    /// Converts any value to a string. Strings are returned
+    /// without quotes, all other values as their compact JSON
+    /// serialization, e.g. `null`, `true`, `1.5` or `[1,2]`.
+    pub fn coerce_to_string(&self) -> String {
+        match self.as_str() {
+            Some(s) => s.to_string(),
+            None => print_json(self)
+        }
+    }
+
+    /// Encodes a flat object as URL query parameters, e.g.
+    /// `a=1&b=x%20y`, with keys in sorted order. Strings are
+    /// used as-is, other scalars as their JSON text and `null`
+    /// as an empty value. Fails with `TypeMismatch` if the value
+    /// isn't an object or has array or object members.
+    pub fn to_query_string(&self) -> Result<String, JsonError> {
+        if !matches!(*self, Object(_)) {
+            return Err(JsonError::new(TypeMismatch));
+        }
+        let mut pairs = Vec::new();
+        for (k, v) in self.sorted_entries() {
+            let value = match *v {
+                Array(_) | Object(_) => return Err(JsonError::new(TypeMismatch)),
+                Null => String::new(),
+                ref v => v.coerce_to_string()
+            };
+            pairs.push(format!("{}={}", percent_encode(k), percent_encode(&value)));
+        }
+        Ok(pairs.join("&"))
+    }
+
+    /// Number of bytes the compact serialization of the value
+    /// takes, computed without building the output.
+    pub fn serialized_len(&self) -> usize {
+        match *self {
+            Null => 4,
+            Bool(b) => if b { 4 } else { 5 },
+            Num(n) if !n.is_finite() => 4,
+            Num(n) => format!("{}", n).len(),
+            U64(n) => format!("{}", n).len(),
+            RawNumber(ref s) => s.len(),
+            Dec(ref d) => d.to_string().len(),
+            Str(_) | SharedStr(_) => escaped_len(self.as_str().unwrap()) + 2,
+            Array(ref values) => {
+                let commas = values.len().saturating_sub(1);
+                2 + commas + values.iter().map(JsonValue::serialized_len).sum::<usize>()
+            },
+            Object(ref map) => {
+                let commas = map.len().saturating_sub(1);
+                // Each member adds two quotes and a colon.
+                2 + commas + map.iter()
+                    .map(|(k, v)| escaped_len(k) + 3 + v.serialized_len())
+                    .sum::<usize>()
+            }
+        }
+    }
+
+    /// Estimated number of heap bytes owned by the value:
+    /// string capacities, array buffers and one key/value slot
+    /// per object member, recursively. Hash table bookkeeping
+    /// isn't included, nor the value itself.
+    pub fn memory_size(&self) -> usize {
+        use std::mem::size_of;
+        match *self {
+            Null | Bool(_) | Num(_) | U64(_) | Dec(_) => 0,
+            RawNumber(ref s) | Str(ref s) => s.capacity(),
+            // The shared allocation also holds both reference counts
+            SharedStr(ref s) => s.len() + 2 * size_of::<usize>(),
+            Array(ref values) => {
+                values.capacity() * size_of::<JsonValue>() +
+                    values.iter().map(JsonValue::memory_size).sum::<usize>()
+            },
+            Object(ref map) => {
+                map.iter()
+                    .map(|(k, v)| size_of::<(Arc<str>, JsonValue)>() + k.len() + 2 * size_of::<usize>() + v.memory_size())
+                    .sum()
+            }
+        }
+    }
+
     pub fn into_array(self) -> Option<Vec<JsonValue>> {
         match self {
             Array(vec) => Some(vec),
@@ -63,579 +267,5575 @@ impl JsonValue {
         }
     }
     
-    pub fn into_object(self) -> Option<HashMap<String, JsonValue>> {
+    pub fn into_object(self) -> Option<Map> {
         match self {
             Object(map) => Some(map),
             _ => None
         }
     }
-}
 
-fn print_json(value: &JsonValue) -> String {
-    let mut result = String::new();
+    /// Recursively collects every node, containers included,
+    /// for which `predicate` returns true. A node is visited
+    /// before its children.
+    pub fn find_all(&self, predicate: impl Fn(&JsonValue) -> bool) -> Vec<&JsonValue> {
+        let mut result = Vec::new();
+        find_all_into(self, &predicate, &mut result);
+        result
+    }
 
-    match *value {
-        Null => result.push_str("null"),
-        Bool(b) => result.push_str(&format!("{}", b)),
-        Num(n) => result.push_str(&format!("{}", n)),
-        Str(ref s) => result.push_str(&format!("{:?}", s)),
-        Array(ref values) => {
-            result.push('[');
-            for v in values.iter() {
-                result.push_str(&print_json(v));
-                result.push(',');
-            }
-            result.pop();
-            result.push(']');
-        },
-        Object(ref map) => {
-            result.push('{');
-            for (k, v) in map.iter() {
-                result.push_str(&format!("{:?}", k));
-                result.push(':');
-                result.push_str(&print_json(v));
-                result.push(',');
+    /// Evaluates a JSONPath expression and returns all matching
+    /// nodes. Supported are the root `$`, `.key` and `['key']`,
+    /// `[index]`, the wildcards `.*` and `[*]` and recursive
+    /// descent `..`, e.g. `$.users[*].name` or `$..id`.
+    pub fn query(&self, expr: &str) -> Result<Vec<&JsonValue>, JsonError> {
+        let mut current = vec![self];
+        for step in parse_query(expr)? {
+            let mut next = Vec::new();
+            for node in current {
+                if step.recursive {
+                    for descendant in node.find_all(|_| true) {
+                        step.selector.select(descendant, &mut next);
+                    }
+                } else {
+                    step.selector.select(node, &mut next);
+                }
             }
-            result.pop();
-            result.push('}');
+            current = next;
         }
+        Ok(current)
     }
-    
-    result
-}
 
-/// Indexing a JSON array
-impl Index<usize> for JsonValue {
-    type Output = JsonValue;
-    fn index(&self, index: usize) -> &JsonValue {
-	match self {
-	    &Array(ref vec) => &vec[index],
-	    _ => panic!("Can only index arrays with usize!")
-	}
+    /// Returns a copy where every array or object nested
+    /// `max_depth` or more levels deep is replaced with
+    /// `"..."`, for previews and logging. The value itself is
+    /// at depth 0, so `max_depth` 0 truncates any container.
+    pub fn truncate_depth(&self, max_depth: usize) -> JsonValue {
+        match *self {
+            Array(_) | Object(_) if max_depth == 0 => Str("...".to_string()),
+            Array(ref values) => Array(values.iter().map(|v| v.truncate_depth(max_depth - 1)).collect()),
+            Object(ref map) => Object(map.iter()
+                                      .map(|(k, v)| (k.clone(), v.truncate_depth(max_depth - 1)))
+                                      .collect()),
+            ref v => v.clone()
+        }
     }
-}
 
-/// Indexing a JSON object
-impl<'a> Index<&'a str> for JsonValue {
-    type Output = JsonValue;
-    fn index(&self, idx: &str) -> &JsonValue {
-	self.find(idx).expect("Can only index objects with &str!")
+    /// Members of an object sorted by key, or an empty Vec
+    /// for any other value.
+    pub fn sorted_entries(&self) -> Vec<(&Arc<str>, &JsonValue)> {
+        match *self {
+            Object(ref map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                entries
+            },
+            _ => Vec::new()
+        }
     }
-}
-// TODO
-impl fmt::Display for JsonValue {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", print_json(self))
+
+    /// Collects every string value in the tree in the order
+    /// `find_all` visits them. Object keys are not included.
+    pub fn string_values(&self) -> Vec<&str> {
+        self.find_all(|v| v.as_str().is_some()).into_iter()
+            .filter_map(JsonValue::as_str)
+            .collect()
     }
-}
 
-/// Stores an error code and line/column information
-/// about where the error occurred for better debugging.
-#[derive(Debug, PartialEq)]
-pub struct JsonError {
-    pub reason: ErrorCode,
-    pub line: usize,
-    pub col: usize
-}
+    /// Checks whether two values have the same structure,
+    /// ignoring the values themselves: the same kind of value
+    /// at each position and the same key sets in objects.
+    /// Arrays may differ in length; only the elements both
+    /// have are compared. See `same_shape_with`.
+    pub fn same_shape(&self, other: &JsonValue) -> bool {
+        self.same_shape_with(other, false)
+    }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum ErrorCode {
-    UnclosedStringLiteral,
-    UnclosedArray,
-    UnclosedObject,
-    MissingColon,
-    ExpectedBool,
-    NumberParsing,
-    ExpectedColon,
-    EndOfFile,
-    ExpectedNull,
-    Other
-}
+    /// Like `same_shape`, but if `equal_array_lengths` is set,
+    /// arrays must also have the same number of elements.
+    pub fn same_shape_with(&self, other: &JsonValue, equal_array_lengths: bool) -> bool {
+        match (self, other) {
+            (Array(a), Array(b)) => {
+                (!equal_array_lengths || a.len() == b.len()) &&
+                    a.iter().zip(b).all(|(x, y)| x.same_shape_with(y, equal_array_lengths))
+            },
+            (Object(a), Object(b)) => {
+                a.len() == b.len() && a.iter().all(|(k, x)| {
+                    b.get(k).is_some_and(|y| x.same_shape_with(y, equal_array_lengths))
+                })
+            },
+            (a, b) => a.type_name() == b.type_name()
+        }
+    }
 
-impl ErrorCode {
-    pub fn description(&self) -> &str {
-        match *self {           
-            ErrorCode::UnclosedStringLiteral => "Unclosed string literal",
-            ErrorCode::UnclosedArray => "Unclosed array bracket",
-            ErrorCode::UnclosedObject => "Unclosed object bracket",
-            ErrorCode::MissingColon => "Missing colon",
-            ErrorCode::ExpectedBool => "Expected true or false",
-            ErrorCode::NumberParsing => "Error parsing number",
-            ErrorCode::ExpectedColon => "Expected colon",
-            ErrorCode::EndOfFile => "End of file reached",
-            ErrorCode::ExpectedNull => "Expected null",
-            ErrorCode::Other => "Unknown error"
+    /// Returns a copy keeping at most `max_keys` members of each
+    /// object (the first ones in key order) and `max_array`
+    /// elements of each array, for log summaries. Where something
+    /// was cut, a marker saying how much is added: an extra
+    /// `"..."` member in objects, a last `"... N more"` element
+    /// in arrays.
+    pub fn sample(&self, max_keys: usize, max_array: usize) -> JsonValue {
+        match *self {
+            Array(ref values) => {
+                let mut result: Vec<JsonValue> = values.iter().take(max_array)
+                    .map(|v| v.sample(max_keys, max_array))
+                    .collect();
+                if values.len() > max_array {
+                    result.push(Str(format!("... {} more", values.len() - max_array)));
+                }
+                Array(result)
+            },
+            Object(ref map) => {
+                let mut result: Map = self.sorted_entries().into_iter().take(max_keys)
+                    .map(|(k, v)| (k.clone(), v.sample(max_keys, max_array)))
+                    .collect();
+                if map.len() > max_keys {
+                    result.insert("...".into(), Str(format!("{} more", map.len() - max_keys)));
+                }
+                Object(result)
+            },
+            ref v => v.clone()
         }
     }
-}
 
+    /// Returns a copy with every `Num` rounded to `decimals`
+    /// decimal places, e.g. to keep measurement output stable.
+    pub fn round_floats(&self, decimals: u32) -> JsonValue {
+        match *self {
+            Num(n) => {
+                // Values that can't be scaled without overflowing
+                // already have fewer than `decimals` decimal places.
+                let factor = 10f64.powi(decimals.min(i32::MAX as u32) as i32);
+                let scaled = n * factor;
+                if factor.is_finite() && scaled.is_finite() {
+                    Num(scaled.round() / factor)
+                } else {
+                    Num(n)
+                }
+            },
+            Array(ref values) => Array(values.iter().map(|v| v.round_floats(decimals)).collect()),
+            Object(ref map) => Object(map.iter().map(|(k, v)| (k.clone(), v.round_floats(decimals))).collect()),
+            ref v => v.clone()
+        }
+    }
 
-impl fmt::Display for JsonError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}:{} error: {}", self.line, self.col, self.reason.description())
+    /// Whether the value is an array with an element equal to
+    /// `needle`. False for every other kind of value.
+    pub fn array_contains(&self, needle: &JsonValue) -> bool {
+        match *self {
+            Array(ref values) => values.contains(needle),
+            _ => false
+        }
     }
-}
 
-/// Result of most parsing functions. Either we succeed in parsing
-/// and a value is returned or ther was an error and we return
-/// an error code.
-pub type JsonResult = Result<JsonValue, JsonError>;
+    /// Deep-merges `other` into this value: object members are
+    /// merged recursively, anything else is replaced by `other`.
+    pub fn merge(&mut self, other: JsonValue) {
+        self.merge_with(other, ArrayMergeStrategy::Replace)
+    }
 
-/// The parser stores an iterator over characters,
-/// information about the current position (line/col)
-/// and the current character.
-pub struct JsonParser<T> {
-    iter: T,
-    line: usize,
-    col: usize,
-    ch: Option<char>
-}
+    /// Like `merge`, but arrays present on both sides are combined
+    /// according to `array_strategy`.
+    pub fn merge_with(&mut self, other: JsonValue, array_strategy: ArrayMergeStrategy) {
+        match (self, other) {
+            (&mut Object(ref mut map), Object(other)) => {
+                for (k, v) in other {
+                    match map.entry(k) {
+                        Entry::Occupied(mut e) => e.get_mut().merge_with(v, array_strategy),
+                        Entry::Vacant(e) => { e.insert(v); }
+                    }
+                }
+            },
+            (&mut Array(ref mut values), Array(other)) if array_strategy == ArrayMergeStrategy::Concat => {
+                values.extend(other);
+            },
+            (this, other) => *this = other
+        }
+    }
 
-impl<T: Iterator<Item = char>> JsonParser<T> {
-    pub fn new(input: T) -> JsonParser<T> {
-        let mut parser = JsonParser {
-            iter: input,
-            line: 1,
-            col: 0,
-            ch: Some('\x00')
+    /// Removes repeated elements from an array, keeping the
+    /// first occurrence of each, as compared by `==`. Does
+    /// nothing for other kinds of values.
+    pub fn dedup_array(&mut self) {
+        if let Array(ref mut values) = *self {
+            let mut seen = HashSet::new();
+            values.retain(|v| seen.insert(v.clone()));
+        }
+    }
+
+    /// Applies a JSON Merge Patch (RFC 7386): object members
+    /// of `patch` are merged in recursively, a `null` member
+    /// removes the key, and any other patch replaces the value.
+    pub fn apply_merge_patch(&mut self, patch: &JsonValue) {
+        let patch_map = match *patch {
+            Object(ref map) => map,
+            ref other => {
+                *self = other.clone();
+                return;
+            }
         };
-        parser.consume_char();
-        parser
+        if !matches!(*self, Object(_)) {
+            *self = Object(Map::new());
+        }
+        if let Object(ref mut map) = *self {
+            for (k, v) in patch_map {
+                match *v {
+                    Null => { map.remove(k); },
+                    _ => map.entry(k.clone()).or_insert(Null).apply_merge_patch(v)
+                }
+            }
+        }
     }
 
-    fn error(&self, reason: ErrorCode) -> JsonResult {
-        Err(JsonError {
-            reason: reason,
-            line: self.line,
-            col: self.col
-        })
+    /// Replaces every object key, at any depth, with `f(key)`,
+    /// e.g. to convert snake_case keys to camelCase. When several
+    /// keys of an object end up with the same name, the value of
+    /// the one that sorted last before renaming wins.
+    pub fn rename_keys<F: Fn(&str) -> String>(&mut self, f: F) {
+        self.rename_keys_with(&f)
     }
 
-    // Advances the character iterator by one and returns the new character
-    #[inline]
-    fn consume_char(&mut self) -> char {
-        self.ch = self.iter.next();
-        if self.ch_is('\n') {
-            self.line += 1;
-            self.col = 1;
-        } else {
-            self.col += 1;
+    fn rename_keys_with<F: Fn(&str) -> String>(&mut self, f: &F) {
+        match *self {
+            Array(ref mut values) => {
+                for v in values {
+                    v.rename_keys_with(f);
+                }
+            },
+            Object(ref mut map) => {
+                let mut entries: Vec<_> = mem::take(map).into_iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                for (k, mut v) in entries {
+                    v.rename_keys_with(f);
+                    map.insert(f(&k).into(), v);
+                }
+            },
+            _ => {}
         }
-        self.ch.unwrap_or('\x00')
     }
 
-    // Is the current character equal to c?
-    #[inline]
-    fn ch_is(&self, c: char) -> bool {
-        self.ch == Some(c)
+    /// Recursively removes object members that are `null` (if
+    /// `remove_null`) or empty arrays and objects (if
+    /// `remove_empty`). Children are pruned first, so a member
+    /// that only becomes empty through pruning is removed too.
+    /// Array elements are pruned inside but never removed.
+    pub fn prune(&mut self, remove_null: bool, remove_empty: bool) {
+        match *self {
+            Array(ref mut values) => {
+                for v in values {
+                    v.prune(remove_null, remove_empty);
+                }
+            },
+            Object(ref mut map) => {
+                for v in map.values_mut() {
+                    v.prune(remove_null, remove_empty);
+                }
+                map.retain(|_, v| match *v {
+                    Null => !remove_null,
+                    Array(ref values) => !(remove_empty && values.is_empty()),
+                    Object(ref map) => !(remove_empty && map.is_empty()),
+                    _ => true
+                });
+            },
+            _ => {}
+        }
     }
 
-    // Are we at the end of the file?
-    #[inline]
-    fn eof(&self) -> bool {
-        self.ch.is_none()
+    /// Structural equality where numbers only have to be
+    /// within `epsilon` of each other.
+    pub fn approx_eq(&self, other: &JsonValue, epsilon: f64) -> bool {
+        // Only the number variants have an f64 value.
+        if let (Some(a), Some(b)) = (self.as_f64(), other.as_f64()) {
+            return (a - b).abs() <= epsilon;
+        }
+        match (self, other) {
+            (Array(a), Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.approx_eq(y, epsilon))
+            },
+            (Object(a), Object(b)) => {
+                a.len() == b.len() &&
+                    a.iter().all(|(k, x)| b.get(k).is_some_and(|y| x.approx_eq(y, epsilon)))
+            },
+            (a, b) => a == b
+        }
     }
 
-    // Advances the input by the length of the passed text.
-    // If one of the characters in the input is not equal
-    // to the corresponding character in the text, returns None.
-    fn consume_text(&mut self, text: &str) -> Option<String> {
-        let mut buf = String::new();
-        self.consume_whitespace();
-
-        for c in text.chars() {
-            if !self.ch_is(c) {
-                return None;
-            }
-            let d = self.consume_char();
-            buf.push(d);
-            
+    /// Returns the value with all arrays, at any depth, sorted
+    /// by a canonical order: nulls < bools < numbers < strings
+    /// < arrays < objects.
+    pub fn sorted(self) -> JsonValue {
+        match self {
+            Array(values) => {
+                let mut values: Vec<JsonValue> = values.into_iter().map(JsonValue::sorted).collect();
+                values.sort_by(cmp_values);
+                Array(values)
+            },
+            Object(map) => Object(map.into_iter().map(|(k, v)| (k, v.sorted())).collect()),
+            v => v
         }
-        self.consume_whitespace();
+    }
 
-        Some(buf)
+    /// Gets the entry for `key` for in-place manipulation,
+    /// like `Map::entry`. Panics if the value is not an object.
+    pub fn entry(&mut self, key: &str) -> Entry<'_, Arc<str>, JsonValue> {
+        match *self {
+            Object(ref mut map) => map.entry(key.into()),
+            _ => panic!("Can only call entry on objects!")
+        }
     }
 
-    #[inline]
-    fn ch_is_digit(&self) -> bool {
-        match self.ch.unwrap_or('\x00') {
-            '0'...'9' => true,
-            _ => false
+    /// Human-readable name of the value's kind, for error
+    /// messages and logging.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Null => "null",
+            Bool(_) => "bool",
+            Num(_) | U64(_) | RawNumber(_) | Dec(_) => "number",
+            Str(_) | SharedStr(_) => "string",
+            Array(_) => "array",
+            Object(_) => "object"
         }
     }
 
-    #[inline]
-    fn ch_is_whitespace(&self) -> bool {
-        self.ch_is(' ') || self.ch_is('\n') ||
-            self.ch_is('\t') || self.ch_is('\r')
+    /// Counts how many values of each kind appear in the tree,
+    /// the value itself included. Keys are `type_name`s.
+    pub fn type_histogram(&self) -> HashMap<&'static str, usize> {
+        let mut counts = HashMap::new();
+        count_types(self, &mut counts);
+        counts
     }
 
-    // Consumes whitespace until the next non-whitespace character is reached
-    #[inline]
-    fn consume_whitespace(&mut self) {
-        while self.ch_is_whitespace() {
-            self.consume_char();
+    /// Replaces every string, at any depth, that is exactly a
+    /// JSON number literal (e.g. `"42"` or `"-1.5e3"`, but not
+    /// `" 42"` or `"0x10"`) with that number.
+    pub fn coerce_numeric_strings(&mut self) {
+        match *self {
+            Str(_) | SharedStr(_) => {
+                let s = self.as_str().unwrap();
+                if is_json_number(s) {
+                    if let Ok(n) = from_str(s) {
+                        *self = n;
+                    }
+                }
+            },
+            Array(ref mut values) => {
+                for v in values {
+                    v.coerce_numeric_strings();
+                }
+            },
+            Object(ref mut map) => {
+                for v in map.values_mut() {
+                    v.coerce_numeric_strings();
+                }
+            },
+            _ => {}
         }
     }
 
-    // Consumes a numerical literal and returns its value as a string.
-    #[inline]
-    fn consume_num(&mut self) -> String {
-        let mut result = String::new();
-        self.consume_whitespace();
+    /// Rewrites every number in the tree into one canonical form,
+    /// so equal numbers compare equal however they were written:
+    /// `RawNumber`s and `Dec`s are converted, integers above
+    /// `i64::MAX` that fit become `U64`, all other numbers `Num`,
+    /// and `-0` is turned into `0`.
+    pub fn normalize_numbers(&mut self) {
+        match *self {
+            Num(_) | U64(_) | RawNumber(_) | Dec(_) => {
+                if let Some(n) = normalized_number(self) {
+                    *self = n;
+                }
+            },
+            Array(ref mut values) => {
+                for v in values {
+                    v.normalize_numbers();
+                }
+            },
+            Object(ref mut map) => {
+                for v in map.values_mut() {
+                    v.normalize_numbers();
+                }
+            },
+            _ => {}
+        }
+    }
 
-        while self.ch_is_digit() || self.ch_is('.') || self.ch_is('e') || self.ch_is('E')
-            || self.ch_is('E') || self.ch_is('-') || self.ch_is('+') {
-                result.push(self.ch.unwrap());
-                self.consume_char();
-            }
+    /// Deterministic 64-bit hash of the document's content,
+    /// stable across runs. Object key order and the way numbers
+    /// were written (`2`, `2.0`, `-0`) don't affect it.
+    pub fn content_hash(&self) -> u64 {
+        let mut value = self.clone();
+        value.normalize_numbers();
+        // The serde_json compatible output sorts object keys. NaN
+        // and infinities are written as literals, not as `null`.
+        let options = SerializerOptions {
+            serde_json_compat: true,
+            allow_nan_infinity: true,
+            ..SerializerOptions::default()
+        };
+        let mut hasher = Fnv1a(0xcbf29ce484222325);
+        to_writer_with_options(&value, &mut hasher, &options).unwrap();
+        hasher.0
+    }
+
+    /// Collects every distinct object key used anywhere
+    /// in the tree.
+    pub fn all_keys(&self) -> BTreeSet<String> {
+        let mut keys = BTreeSet::new();
+        collect_keys(self, &mut keys);
+        keys
+    }
+
+    /// Returns every scalar leaf together with its JSON Pointer
+    /// path, e.g. `/users/0/name`. Object members are visited
+    /// in key order so the output is deterministic.
+    pub fn flatten(&self) -> Vec<(String, JsonValue)> {
+        let mut result = Vec::new();
+        flatten_into(self, String::new(), &mut result);
         result
     }
-    // Parses the JSON null value.
-    fn parse_null(&mut self) -> JsonResult {
-        match self.consume_text("null") {
-            Some(_) => Ok(Null),
-            None => self.error(ExpectedNull)
+
+    /// Iterates depth-first over every node, containers included,
+    /// paired with its JSON Pointer path. A container comes before
+    /// its children and object members are visited in key order.
+    pub fn iter_paths(&self) -> impl Iterator<Item = (String, &JsonValue)> {
+        let mut result = Vec::new();
+        collect_paths(self, String::new(), &mut result);
+        result.into_iter()
+    }
+
+    /// JSON Pointer paths of every node equal to `target`,
+    /// in `iter_paths` order.
+    pub fn paths_to(&self, target: &JsonValue) -> Vec<String> {
+        self.iter_paths()
+            .filter(|&(_, v)| v == target)
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Expands dotted keys like `db.host` into nested objects,
+    /// e.g. for configuration read from environment variables.
+    /// Fails with `PathConflict` if a key is used both for a
+    /// value and as a prefix of other keys.
+    pub fn from_dotted_map(map: HashMap<String, JsonValue>) -> Result<JsonValue, JsonError> {
+        let mut root = Map::new();
+        for (key, value) in map {
+            let tokens: Vec<&str> = key.split('.').collect();
+            insert_dotted(&mut root, &tokens, value)?;
         }
+        Ok(Object(root))
     }
 
-    // Parses a JSON number.
-    fn parse_num(&mut self) -> JsonResult {
-        self.consume_whitespace();
-        
-        if self.ch_is_digit() || self.ch_is('-') {
-            let num_str = self.consume_num();
-            
-            let n = num_str.parse::<f64>();
-            match n {
-                Ok(num) => return Ok(Num(num)),
-                Err(_) => {
-                    return self.error(NumberParsing);
-                }
+    /// Turns an array of objects into one column per key, with
+    /// `Null` wherever a row lacks the key, e.g. `[{"a":1},
+    /// {"a":2,"b":3}]` into `a: [1, 2]` and `b: [null, 3]`.
+    /// Fails with `TypeMismatch` unless every element of the
+    /// array is an object.
+    pub fn to_columns(&self) -> Result<HashMap<String, Vec<JsonValue>>, JsonError> {
+        let rows = match *self {
+            Array(ref rows) => rows,
+            _ => return Err(JsonError::new(TypeMismatch))
+        };
+        let mut columns = HashMap::new();
+        for (i, row) in rows.iter().enumerate() {
+            let map = match *row {
+                Object(ref map) => map,
+                _ => return Err(JsonError::new(TypeMismatch))
+            };
+            for k in map.keys() {
+                columns.entry(k.to_string()).or_insert_with(|| vec![Null; i]);
+            }
+            for (k, column) in columns.iter_mut() {
+                column.push(map.get(k.as_str()).cloned().unwrap_or(Null));
             }
-            
-        } else {
-            self.error(NumberParsing)
         }
+        Ok(columns)
     }
-    
-    // Parses a JSON string value.
-    fn parse_string(&mut self) -> JsonResult {
-        self.consume_whitespace();
-        
-        if self.ch_is('"') {
-            self.consume_char();
-            let mut found_end = false;
-            let mut s = String::new();
-            while !self.eof() {
-                if self.ch_is('"') {
-                    found_end = true;
-                    self.consume_char();
-                    break;
+
+    /// Zips columns back into an array of row objects, the
+    /// inverse of `to_columns`. `Null` entries are kept as
+    /// `null` members. Fails with `LengthMismatch` if the
+    /// columns differ in length.
+    pub fn from_columns(cols: HashMap<String, Vec<JsonValue>>) -> Result<JsonValue, JsonError> {
+        let len = cols.values().next().map_or(0, Vec::len);
+        if cols.values().any(|column| column.len() != len) {
+            return Err(JsonError::new(LengthMismatch));
+        }
+        let mut rows = vec![Map::new(); len];
+        for (k, column) in cols {
+            let key: Arc<str> = k.into();
+            for (row, value) in rows.iter_mut().zip(column) {
+                row.insert(key.clone(), value);
+            }
+        }
+        Ok(Array(rows.into_iter().map(Object).collect()))
+    }
+
+    /// Rebuilds a tree from JSON Pointer paths as produced by
+    /// `flatten`. Containers whose keys are exactly the indices
+    /// `0..n` become arrays, all others become objects. An empty
+    /// list of pairs yields an empty object.
+    pub fn unflatten(pairs: Vec<(String, JsonValue)>) -> Result<JsonValue, JsonError> {
+        let mut root: Option<PathNode> = None;
+        for (path, value) in pairs {
+            let tokens = parse_pointer(&path)?;
+            if tokens.is_empty() {
+                if root.is_some() {
+                    return Err(JsonError::new(PathConflict));
                 }
-                s.push(self.ch.unwrap());
-                self.consume_char();
+                root = Some(PathNode::Leaf(value));
+                continue;
+            }
+            match *root.get_or_insert_with(|| PathNode::Branch(HashMap::new())) {
+                PathNode::Branch(ref mut map) => insert_path(map, &tokens, value)?,
+                PathNode::Leaf(_) => return Err(JsonError::new(PathConflict))
+            }
+        }
+        Ok(root.map_or_else(|| Object(Map::new()), PathNode::into_value))
+    }
+
+    /// Looks up a value by JSON Pointer, e.g. `/users/0/name`.
+    pub fn pointer(&self, path: &str) -> Option<&JsonValue> {
+        let tokens = parse_pointer(path).ok()?;
+        let mut target = self;
+        for token in &tokens {
+            target = match *target {
+                Object(ref map) => map.get(token.as_str())?,
+                Array(ref vec) => vec.get(array_index(token)?)?,
+                _ => return None
+            };
+        }
+        Some(target)
+    }
+
+    /// Mutable version of `pointer`.
+    pub fn pointer_mut(&mut self, path: &str) -> Option<&mut JsonValue> {
+        let tokens = parse_pointer(path).ok()?;
+        let mut target = self;
+        for token in &tokens {
+            target = match *target {
+                Object(ref mut map) => map.get_mut(token.as_str())?,
+                Array(ref mut vec) => vec.get_mut(array_index(token)?)?,
+                _ => return None
+            };
+        }
+        Some(target)
+    }
+
+    /// Removes the value at a JSON Pointer from its parent and
+    /// returns it. Later array elements shift down. Returns
+    /// None if nothing is there, or for the root pointer `""`.
+    pub fn remove_pointer(&mut self, path: &str) -> Option<JsonValue> {
+        let tokens = parse_pointer(path).ok()?;
+        let (last, parents) = tokens.split_last()?;
+        let mut target = self;
+        for token in parents {
+            target = match *target {
+                Object(ref mut map) => map.get_mut(token.as_str())?,
+                Array(ref mut vec) => vec.get_mut(array_index(token)?)?,
+                _ => return None
+            };
+        }
+        match *target {
+            Object(ref mut map) => map.remove(last.as_str()),
+            Array(ref mut vec) => {
+                let index = array_index(last)?;
+                if index < vec.len() { Some(vec.remove(index)) } else { None }
+            },
+            _ => None
+        }
+    }
+
+    /// Replaces the value at each JSON Pointer with `"***"`,
+    /// e.g. before logging. Paths that don't exist are skipped.
+    pub fn redact(&mut self, paths: &[&str]) {
+        for path in paths {
+            if let Some(target) = self.pointer_mut(path) {
+                *target = Str("***".to_string());
+            }
+        }
+    }
+
+    /// Returns whether a value exists at the JSON Pointer.
+    pub fn has_pointer(&self, path: &str) -> bool {
+        self.pointer(path).is_some()
+    }
+
+    /// Sets the value at a JSON Pointer, creating missing
+    /// intermediate containers along the way. A missing container
+    /// becomes an array if the following token is an index or `-`,
+    /// otherwise an object. The `-` token appends to an array and
+    /// is rejected with `InvalidPointer` on an existing object.
+    /// Fails if the path runs into a scalar.
+    pub fn set(&mut self, path: &str, value: JsonValue) -> Result<(), JsonError> {
+        let tokens = parse_pointer(path)?;
+        set_path(self, &tokens, value)
+    }
+}
+
+// Position of a value's kind in the canonical order.
+fn type_rank(value: &JsonValue) -> u8 {
+    match *value {
+        Null => 0,
+        Bool(_) => 1,
+        Num(_) | U64(_) | RawNumber(_) | Dec(_) => 2,
+        Str(_) | SharedStr(_) => 3,
+        Array(_) => 4,
+        Object(_) => 5
+    }
+}
+
+// Total order over values used for canonical sorting. Values
+// of different kinds are ordered by type_rank, arrays compare
+// element-wise and objects compare their members in key order.
+fn cmp_values(a: &JsonValue, b: &JsonValue) -> Ordering {
+    match (a, b) {
+        (&Bool(x), &Bool(y)) => x.cmp(&y),
+        (&U64(x), &U64(y)) => x.cmp(&y),
+        (a, b) if type_rank(a) == 2 && type_rank(b) == 2 => {
+            let (x, y) = (a.as_f64().unwrap_or(f64::NAN), b.as_f64().unwrap_or(f64::NAN));
+            x.total_cmp(&y)
+        },
+        (a, b) if type_rank(a) == 3 && type_rank(b) == 3 => a.as_str().cmp(&b.as_str()),
+        (Array(x), Array(y)) => {
+            for (v, w) in x.iter().zip(y) {
+                match cmp_values(v, w) {
+                    Ordering::Equal => {},
+                    ord => return ord
+                }
+            }
+            x.len().cmp(&y.len())
+        },
+        (Object(x), Object(y)) => {
+            let mut xs: Vec<_> = x.iter().collect();
+            let mut ys: Vec<_> = y.iter().collect();
+            xs.sort_by(|p, q| p.0.cmp(q.0));
+            ys.sort_by(|p, q| p.0.cmp(q.0));
+            for (&(k, v), &(l, w)) in xs.iter().zip(&ys) {
+                match k.cmp(l).then_with(|| cmp_values(v, w)) {
+                    Ordering::Equal => {},
+                    ord => return ord
+                }
+            }
+            xs.len().cmp(&ys.len())
+        },
+        _ => type_rank(a).cmp(&type_rank(b))
+    }
+}
+
+// Escapes a single JSON Pointer reference token (RFC 6901).
+fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+// Splits a JSON Pointer into its unescaped reference tokens.
+fn parse_pointer(path: &str) -> Result<Vec<String>, JsonError> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !path.starts_with('/') {
+        return Err(JsonError::new(InvalidPointer));
+    }
+    Ok(path[1..].split('/')
+       .map(|t| t.replace("~1", "/").replace("~0", "~"))
+       .collect())
+}
+
+// Removes `_` digit separators from a number literal. Each
+// underscore has to sit between two digits, otherwise None
+// is returned.
+fn strip_underscores(num: &str) -> Option<String> {
+    let chars: Vec<char> = num.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            let before = i > 0 && chars[i - 1].is_ascii_digit();
+            let after = i + 1 < chars.len() && chars[i + 1].is_ascii_digit();
+            if !before || !after {
+                return None;
+            }
+        }
+    }
+    Some(chars.into_iter().filter(|&c| c != '_').collect())
+}
+
+// Parses an array index token. Leading zeros are not allowed.
+fn array_index(token: &str) -> Option<usize> {
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) ||
+        !token.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    token.parse().ok()
+}
+
+// The container `set` creates for a missing step, depending
+// on the token that will be looked up in it.
+fn empty_container(next_token: &str) -> JsonValue {
+    if next_token == "-" || array_index(next_token).is_some() {
+        Array(Vec::new())
+    } else {
+        Object(Map::new())
+    }
+}
+
+fn set_path(target: &mut JsonValue, tokens: &[String], value: JsonValue) -> Result<(), JsonError> {
+    let (first, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => {
+            *target = value;
+            return Ok(());
+        }
+    };
+    match *target {
+        Object(_) if first == "-" => Err(JsonError::new(InvalidPointer)),
+        Object(ref mut map) => {
+            if rest.is_empty() {
+                map.insert(first.as_str().into(), value);
+                return Ok(());
             }
-            if found_end {
-                Ok(Str(s))
+            let child = map.entry(first.as_str().into()).or_insert_with(|| empty_container(&rest[0]));
+            set_path(child, rest, value)
+        },
+        Array(ref mut vec) => {
+            let idx = if first == "-" {
+                vec.len()
             } else {
-                self.error(UnclosedStringLiteral)
+                array_index(first).ok_or_else(|| JsonError::new(InvalidPointer))?
+            };
+            if idx > vec.len() {
+                return Err(JsonError::new(InvalidPointer));
+            }
+            if rest.is_empty() {
+                if idx == vec.len() {
+                    vec.push(value);
+                } else {
+                    vec[idx] = value;
+                }
+                return Ok(());
+            }
+            if idx == vec.len() {
+                vec.push(empty_container(&rest[0]));
+            }
+            set_path(&mut vec[idx], rest, value)
+        },
+        _ => Err(JsonError::new(PathConflict))
+    }
+}
+
+// Intermediate tree used by `unflatten`, so that array
+// elements can arrive in any order.
+enum PathNode {
+    Leaf(JsonValue),
+    Branch(HashMap<String, PathNode>)
+}
+
+impl PathNode {
+    fn into_value(self) -> JsonValue {
+        match self {
+            PathNode::Leaf(v) => v,
+            PathNode::Branch(mut map) => {
+                let is_array = !map.is_empty() &&
+                    (0..map.len()).all(|i| map.contains_key(&i.to_string()));
+                if is_array {
+                    Array((0..map.len())
+                          .map(|i| map.remove(&i.to_string()).unwrap().into_value())
+                          .collect())
+                } else {
+                    Object(map.into_iter().map(|(k, v)| (k.into(), v.into_value())).collect())
+                }
             }
         }
-        else {
-            self.error(UnclosedStringLiteral)
+    }
+}
+
+fn insert_path(map: &mut HashMap<String, PathNode>, tokens: &[String], value: JsonValue)
+               -> Result<(), JsonError> {
+    let (first, rest) = tokens.split_first().unwrap();
+    if rest.is_empty() {
+        if map.contains_key(first) {
+            return Err(JsonError::new(PathConflict));
+        }
+        map.insert(first.clone(), PathNode::Leaf(value));
+        return Ok(());
+    }
+    match *map.entry(first.clone()).or_insert_with(|| PathNode::Branch(HashMap::new())) {
+        PathNode::Branch(ref mut child) => insert_path(child, rest, value),
+        PathNode::Leaf(_) => Err(JsonError::new(PathConflict))
+    }
+}
+
+// Inserts a value under the dotted key split into `tokens`,
+// creating objects along the way.
+fn insert_dotted(map: &mut Map, tokens: &[&str], value: JsonValue) -> Result<(), JsonError> {
+    let (first, rest) = tokens.split_first().unwrap();
+    if rest.is_empty() {
+        if map.contains_key(*first) {
+            return Err(JsonError::new(PathConflict));
+        }
+        map.insert((*first).into(), value);
+        return Ok(());
+    }
+    match *map.entry((*first).into()).or_insert_with(|| Object(Map::new())) {
+        Object(ref mut child) => insert_dotted(child, rest, value),
+        _ => Err(JsonError::new(PathConflict))
+    }
+}
+
+// One step of a JSONPath expression, see `query`.
+struct QueryStep {
+    // Whether the step was written with `..` and applies
+    // to every descendant.
+    recursive: bool,
+    selector: QuerySelector
+}
+
+enum QuerySelector {
+    Key(String),
+    Index(usize),
+    Wildcard
+}
+
+impl QuerySelector {
+    // Pushes the children of `value` matched by the selector.
+    fn select<'a>(&self, value: &'a JsonValue, result: &mut Vec<&'a JsonValue>) {
+        match (self, value) {
+            (QuerySelector::Key(key), Object(map)) => result.extend(map.get(key.as_str())),
+            (QuerySelector::Index(i), Array(values)) => result.extend(values.get(*i)),
+            (QuerySelector::Wildcard, Array(values)) => result.extend(values.iter()),
+            (QuerySelector::Wildcard, Object(map)) => result.extend(map.values()),
+            _ => {}
+        }
+    }
+}
+
+// Splits a JSONPath expression into its steps.
+fn parse_query(expr: &str) -> Result<Vec<QueryStep>, JsonError> {
+    let invalid = || JsonError::new(InvalidQuery);
+    let mut rest = expr.strip_prefix('$').ok_or_else(invalid)?;
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        let recursive = rest.starts_with("..");
+        if recursive {
+            rest = &rest[2..];
+        } else if rest.starts_with('.') {
+            rest = &rest[1..];
+        } else if !rest.starts_with('[') {
+            return Err(invalid());
+        }
+        let selector = if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            let inner = &after[..end];
+            rest = &after[end + 1..];
+            let quoted = inner.len() >= 2 &&
+                ((inner.starts_with('\'') && inner.ends_with('\'')) ||
+                 (inner.starts_with('"') && inner.ends_with('"')));
+            if inner == "*" {
+                QuerySelector::Wildcard
+            } else if quoted {
+                QuerySelector::Key(inner[1..inner.len() - 1].to_string())
+            } else {
+                QuerySelector::Index(inner.parse().map_err(|_| invalid())?)
+            }
+        } else if let Some(after) = rest.strip_prefix('*') {
+            rest = after;
+            QuerySelector::Wildcard
+        } else {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            let key = rest[..end].to_string();
+            rest = &rest[end..];
+            QuerySelector::Key(key)
+        };
+        steps.push(QueryStep { recursive, selector });
+    }
+    Ok(steps)
+}
+
+fn find_all_into<'a, F>(value: &'a JsonValue, predicate: &F, result: &mut Vec<&'a JsonValue>)
+    where F: Fn(&JsonValue) -> bool {
+    if predicate(value) {
+        result.push(value);
+    }
+    match *value {
+        Array(ref values) => {
+            for v in values {
+                find_all_into(v, predicate, result);
+            }
+        },
+        Object(ref map) => {
+            for v in map.values() {
+                find_all_into(v, predicate, result);
+            }
+        },
+        _ => {}
+    }
+}
+
+fn collect_paths<'a>(value: &'a JsonValue, path: String, result: &mut Vec<(String, &'a JsonValue)>) {
+    result.push((path.clone(), value));
+    match *value {
+        Array(ref values) => {
+            for (i, v) in values.iter().enumerate() {
+                collect_paths(v, format!("{}/{}", path, i), result);
+            }
+        },
+        Object(ref map) => {
+            let mut keys: Vec<&Arc<str>> = map.keys().collect();
+            keys.sort();
+            for k in keys {
+                collect_paths(&map[k], format!("{}/{}", path, escape_pointer_token(k)), result);
+            }
+        },
+        _ => {}
+    }
+}
+
+fn intern_into(value: &mut JsonValue, table: &mut HashSet<Arc<str>>) {
+    match *value {
+        Str(ref s) => {
+            let shared = intern_str(s, table);
+            *value = SharedStr(shared);
+        },
+        Array(ref mut values) => {
+            for v in values {
+                intern_into(v, table);
+            }
+        },
+        Object(ref mut map) => {
+            *map = mem::take(map).into_iter()
+                .map(|(k, mut v)| {
+                    intern_into(&mut v, table);
+                    (intern_str(&k, table), v)
+                })
+                .collect();
+        },
+        _ => {}
+    }
+}
+
+// Returns the table's copy of `s`, adding one if needed.
+fn intern_str(s: &str, table: &mut HashSet<Arc<str>>) -> Arc<str> {
+    match table.get(s) {
+        Some(shared) => shared.clone(),
+        None => {
+            let shared: Arc<str> = Arc::from(s);
+            table.insert(shared.clone());
+            shared
+        }
+    }
+}
+
+// 64-bit FNV-1a hash over everything written to it,
+// used by content_hash.
+struct Fnv1a(u64);
+
+impl Write for Fnv1a {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Canonical form of a number for normalize_numbers.
+fn normalized_number(value: &JsonValue) -> Option<JsonValue> {
+    let n = match *value {
+        U64(n) => return Some(U64(n)),
+        RawNumber(ref s) => match s.parse::<u64>() {
+            Ok(n) => return Some(if n > i64::MAX as u64 { U64(n) } else { Num(n as f64) }),
+            Err(_) => s.parse::<f64>().ok()?
+        },
+        Num(n) => n,
+        Dec(ref d) => match d.to_string().parse::<u64>() {
+            Ok(n) => return Some(if n > i64::MAX as u64 { U64(n) } else { Num(n as f64) }),
+            Err(_) => d.to_f64()
+        },
+        _ => return None
+    };
+    if n == 0.0 {
+        Some(Num(0.0))
+    } else if n.fract() == 0.0 && n >= i64::MAX as f64 && n < u64::MAX as f64 {
+        Some(U64(n as u64))
+    } else {
+        Some(Num(n))
+    }
+}
+
+// Recursive helper for all_keys.
+fn collect_keys(value: &JsonValue, keys: &mut BTreeSet<String>) {
+    match *value {
+        Array(ref values) => {
+            for v in values {
+                collect_keys(v, keys);
+            }
+        },
+        Object(ref map) => {
+            for (k, v) in map {
+                if !keys.contains(&**k) {
+                    keys.insert(k.to_string());
+                }
+                collect_keys(v, keys);
+            }
+        },
+        _ => {}
+    }
+}
+
+// Recursive helper for type_histogram.
+fn count_types(value: &JsonValue, counts: &mut HashMap<&'static str, usize>) {
+    *counts.entry(value.type_name()).or_insert(0) += 1;
+    match *value {
+        Array(ref values) => {
+            for v in values {
+                count_types(v, counts);
+            }
+        },
+        Object(ref map) => {
+            for v in map.values() {
+                count_types(v, counts);
+            }
+        },
+        _ => {}
+    }
+}
+
+fn flatten_into(value: &JsonValue, path: String, result: &mut Vec<(String, JsonValue)>) {
+    match *value {
+        Array(ref values) => {
+            for (i, v) in values.iter().enumerate() {
+                flatten_into(v, format!("{}/{}", path, i), result);
+            }
+        },
+        Object(ref map) => {
+            let mut keys: Vec<&Arc<str>> = map.keys().collect();
+            keys.sort();
+            for k in keys {
+                flatten_into(&map[k], format!("{}/{}", path, escape_pointer_token(k)), result);
+            }
+        },
+        ref leaf => result.push((path, leaf.clone()))
+    }
+}
+
+// Checks `s` against the JSON number grammar:
+// -?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+fn is_json_number(s: &str) -> bool {
+    fn digits(s: &str) -> (&str, usize) {
+        let n = s.bytes().take_while(u8::is_ascii_digit).count();
+        (&s[n..], n)
+    }
+    let s = s.strip_prefix('-').unwrap_or(s);
+    let (mut rest, n) = digits(s);
+    if n == 0 || (n > 1 && s.starts_with('0')) {
+        return false;
+    }
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let (after, n) = digits(fraction);
+        if n == 0 {
+            return false;
+        }
+        rest = after;
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let (after, n) = digits(exponent);
+        if n == 0 {
+            return false;
+        }
+        rest = after;
+    }
+    rest.is_empty()
+}
+
+// Percent-encodes everything except the RFC 3986 unreserved
+// characters, byte by byte in UTF-8.
+fn percent_encode(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => result.push(b as char),
+            _ => result.push_str(&format!("%{:02X}", b))
+        }
+    }
+    result
+}
+
+// Escapes a string for use inside a JSON string literal.
+// Borrows the input if nothing needs escaping.
+fn escape_str(s: &str) -> Cow<'_, str> {
+    if !s.chars().any(|c| c == '"' || c == '\\' || c < '\x20') {
+        return Cow::Borrowed(s);
+    }
+    let mut result = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\x08' => result.push_str("\\b"),
+            '\x0c' => result.push_str("\\f"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c < '\x20' => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c)
+        }
+    }
+    Cow::Owned(result)
+}
+
+// Length of a string after `escape_str`, without the quotes.
+fn escaped_len(s: &str) -> usize {
+    s.chars().map(|c| match c {
+        '"' | '\\' | '\x08' | '\x0c' | '\n' | '\r' | '\t' => 2,
+        c if c < '\x20' => 6,
+        c => c.len_utf8()
+    }).sum()
+}
+
+fn print_json(value: &JsonValue) -> String {
+    // The serializer only ever writes valid UTF-8.
+    String::from_utf8(to_bytes(value)).unwrap()
+}
+
+/// Options for the compact serializer.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SerializerOptions {
+    /// Match `serde_json::to_string` byte for byte: object keys
+    /// are written in sorted order (serde_json's default map is
+    /// a BTreeMap), integral numbers are written as integers,
+    /// other floats use serde_json's shortest form with an
+    /// exponent for very large or small values (`1e20`, `1e-7`),
+    /// `-0` is written as `-0.0` and NaN and infinities as `null`.
+    pub serde_json_compat: bool,
+    /// Write `/` in strings as `\/`, which some consumers
+    /// embedding JSON in HTML expect. Off by default since
+    /// the escape is optional.
+    pub escape_forward_slashes: bool,
+    /// Write NaN and infinities as the `NaN`, `Infinity` and
+    /// `-Infinity` literals that `ParserOptions::allow_nan_infinity`
+    /// reads, even in serde_json compatible mode. By default
+    /// they are written as `null`, since JSON can't express them.
+    pub allow_nan_infinity: bool
+}
+
+/// Writes the compact serialization of a value to `w`.
+/// Negative zero keeps its sign and is written as `-0`.
+pub fn to_writer<W: Write>(value: &JsonValue, w: &mut W) -> io::Result<()> {
+    to_writer_with_options(value, w, &SerializerOptions::default())
+}
+
+/// Writes the compact serialization of a value to `w`
+/// using the given options.
+pub fn to_writer_with_options<W: Write>(value: &JsonValue, w: &mut W, options: &SerializerOptions)
+                                        -> io::Result<()> {
+    match *value {
+        Null => w.write_all(b"null"),
+        Bool(b) => write!(w, "{}", b),
+        Num(n) if !n.is_finite() => w.write_all(non_finite_literal(n, options).as_bytes()),
+        Num(n) if options.serde_json_compat => w.write_all(format_serde_f64(n).as_bytes()),
+        Num(n) => write!(w, "{}", n),
+        U64(n) => write!(w, "{}", n),
+        RawNumber(ref s) => w.write_all(s.as_bytes()),
+        Dec(ref d) => write!(w, "{}", d),
+        Str(_) | SharedStr(_) => write_str(value.as_str().unwrap(), w, options),
+        Array(ref values) => {
+            w.write_all(b"[")?;
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b",")?;
+                }
+                to_writer_with_options(v, w, options)?;
+            }
+            w.write_all(b"]")
+        },
+        Object(ref map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            if options.serde_json_compat {
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+            }
+            w.write_all(b"{")?;
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b",")?;
+                }
+                write_str(k, w, options)?;
+                w.write_all(b":")?;
+                to_writer_with_options(v, w, options)?;
+            }
+            w.write_all(b"}")
         }
     }
+}
+
+// Text for NaN and the infinities, which have no JSON literal.
+fn non_finite_literal(n: f64, options: &SerializerOptions) -> &'static str {
+    if !options.allow_nan_infinity {
+        "null"
+    } else if n.is_nan() {
+        "NaN"
+    } else if n > 0.0 {
+        "Infinity"
+    } else {
+        "-Infinity"
+    }
+}
+
+// Writes a quoted, escaped string literal.
+fn write_str<W: Write>(s: &str, w: &mut W, options: &SerializerOptions) -> io::Result<()> {
+    let escaped = escape_str(s);
+    if options.escape_forward_slashes {
+        write!(w, "\"{}\"", escaped.replace('/', "\\/"))
+    } else {
+        write!(w, "\"{}\"", escaped)
+    }
+}
+
+/// Returns the compact serialization of a value using the
+/// given options.
+pub fn to_string_with_options(value: &JsonValue, options: &SerializerOptions) -> String {
+    let mut result = Vec::new();
+    to_writer_with_options(value, &mut result, options).unwrap();
+    String::from_utf8(result).unwrap()
+}
+
+// Formats a float the way serde_json does.
+fn format_serde_f64(n: f64) -> String {
+    if !n.is_finite() {
+        return "null".to_string();
+    }
+    if n.fract() == 0.0 && n.abs() < 9.2e18 && !(n == 0.0 && n.is_sign_negative()) {
+        return format!("{}", n as i64);
+    }
+    // Shortest round-trip digits and the decimal exponent,
+    // laid out like the ryu crate does.
+    let sci = format!("{:e}", n.abs());
+    let (mantissa, exp) = sci.split_at(sci.find('e').unwrap());
+    let digits = mantissa.replace('.', "");
+    let length = digits.len() as i32;
+    let kk = exp[1..].parse::<i32>().unwrap() + 1;
+    let k = kk - length;
+
+    let mut result = String::new();
+    if n.is_sign_negative() {
+        result.push('-');
+    }
+    if 0 <= k && kk <= 16 {
+        result.push_str(&digits);
+        result.push_str(&"0".repeat(k as usize));
+        result.push_str(".0");
+    } else if 0 < kk && kk <= 16 {
+        result.push_str(&digits[..kk as usize]);
+        result.push('.');
+        result.push_str(&digits[kk as usize..]);
+    } else if -5 < kk && kk <= 0 {
+        result.push_str("0.");
+        result.push_str(&"0".repeat(-kk as usize));
+        result.push_str(&digits);
+    } else if length == 1 {
+        result.push_str(&format!("{}e{}", digits, kk - 1));
+    } else {
+        result.push_str(&format!("{}.{}e{}", &digits[..1], &digits[1..], kk - 1));
+    }
+    result
+}
+
+/// Indentation used by the pretty-printer for each nesting level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indent {
+    Spaces(usize),
+    Tabs
+}
+
+/// Writes the value to `w`, putting each array element and
+/// object member on its own line indented by `indent` spaces
+/// per nesting level.
+pub fn to_pretty_writer<W: Write>(value: &JsonValue, w: &mut W, indent: usize) -> io::Result<()> {
+    to_pretty_writer_with_indent(value, w, Indent::Spaces(indent))
+}
+
+/// Like `to_pretty_writer`, but indents with the given style,
+/// e.g. one tab per nesting level.
+pub fn to_pretty_writer_with_indent<W: Write>(value: &JsonValue, w: &mut W, indent: Indent)
+                                              -> io::Result<()> {
+    write_pretty(value, w, indent, 0)
+}
+
+/// Returns the pretty-printed serialization of a value,
+/// see `to_pretty_writer`.
+pub fn to_pretty_string(value: &JsonValue, indent: usize) -> String {
+    to_pretty_string_with_indent(value, Indent::Spaces(indent))
+}
+
+/// Returns the pretty-printed serialization of a value,
+/// see `to_pretty_writer_with_indent`.
+pub fn to_pretty_string_with_indent(value: &JsonValue, indent: Indent) -> String {
+    let mut result = Vec::new();
+    to_pretty_writer_with_indent(value, &mut result, indent).unwrap();
+    String::from_utf8(result).unwrap()
+}
+
+fn write_indent<W: Write>(w: &mut W, indent: Indent, level: usize) -> io::Result<()> {
+    match indent {
+        Indent::Spaces(width) => write!(w, "\n{:1$}", "", width * level),
+        Indent::Tabs => write!(w, "\n{}", "\t".repeat(level))
+    }
+}
+
+fn write_pretty<W: Write>(value: &JsonValue, w: &mut W, indent: Indent, level: usize) -> io::Result<()> {
+    match *value {
+        Array(ref values) if !values.is_empty() => {
+            w.write_all(b"[")?;
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b",")?;
+                }
+                write_indent(w, indent, level + 1)?;
+                write_pretty(v, w, indent, level + 1)?;
+            }
+            write_indent(w, indent, level)?;
+            w.write_all(b"]")
+        },
+        Object(ref map) if !map.is_empty() => {
+            w.write_all(b"{")?;
+            for (i, (k, v)) in map.iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b",")?;
+                }
+                write_indent(w, indent, level + 1)?;
+                write!(w, "\"{}\": ", escape_str(k))?;
+                write_pretty(v, w, indent, level + 1)?;
+            }
+            write_indent(w, indent, level)?;
+            w.write_all(b"}")
+        },
+        // Scalars and empty containers look the same as in compact output
+        ref v => to_writer(v, w)
+    }
+}
+
+/// Serializes each element of a top-level array as compact JSON
+/// on its own line (newline-delimited JSON).
+pub fn to_ndjson(value: &JsonValue) -> Result<String, JsonError> {
+    match *value {
+        Array(ref values) => {
+            let mut result = String::new();
+            for v in values {
+                result.push_str(&print_json(v));
+                result.push('\n');
+            }
+            Ok(result)
+        },
+        _ => Err(JsonError::new(TypeMismatch))
+    }
+}
+
+/// Returns the compact serialization of a value as UTF-8 bytes.
+pub fn to_bytes(value: &JsonValue) -> Vec<u8> {
+    let mut result = Vec::new();
+    // Writing into a Vec can't fail.
+    to_writer(value, &mut result).unwrap();
+    result
+}
+
+/// The default value is `null`.
+impl Default for JsonValue {
+    fn default() -> JsonValue {
+        Null
+    }
+}
+
+/// Structural equality, except that `Str` and `SharedStr`
+/// are equal if they hold the same text.
+impl PartialEq for JsonValue {
+    fn eq(&self, other: &JsonValue) -> bool {
+        match (self, other) {
+            (&Null, &Null) => true,
+            (&Bool(a), &Bool(b)) => a == b,
+            (&Num(a), &Num(b)) => a == b,
+            (&U64(a), &U64(b)) => a == b,
+            (RawNumber(a), RawNumber(b)) => a == b,
+            (Dec(a), Dec(b)) => a == b,
+            (Array(a), Array(b)) => a == b,
+            (Object(a), Object(b)) => a == b,
+            (a, b) => a.as_str().is_some() && a.as_str() == b.as_str()
+        }
+    }
+}
+
+/// Values can be used as `HashSet` members or `HashMap` keys.
+/// Note that `NaN` is never equal to itself, so a `NaN` inside
+/// a value makes that value unequal to every other one.
+impl Eq for JsonValue {}
+
+/// Hashing is consistent with `PartialEq`: numbers hash their
+/// bit pattern with `-0` folded into `0` (as `0.0 == -0.0`),
+/// and object members are hashed in sorted key order so the
+/// map's iteration order doesn't matter.
+impl Hash for JsonValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        type_rank(self).hash(state);
+        match *self {
+            Null => {},
+            Bool(b) => b.hash(state),
+            Num(n) => (if n == 0.0 { 0.0f64 } else { n }).to_bits().hash(state),
+            U64(n) => n.hash(state),
+            RawNumber(ref s) => s.hash(state),
+            Dec(ref d) => d.hash(state),
+            Str(ref s) => s.hash(state),
+            SharedStr(ref s) => s.hash(state),
+            Array(ref values) => values.hash(state),
+            Object(ref map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                entries.hash(state);
+            }
+        }
+    }
+}
+
+/// Indexing a JSON array
+impl Index<usize> for JsonValue {
+    type Output = JsonValue;
+    fn index(&self, index: usize) -> &JsonValue {
+	match *self {
+	    Array(ref vec) => &vec[index],
+	    _ => panic!("Can only index arrays with usize!")
+	}
+    }
+}
+
+/// Indexing a JSON object
+impl Index<&str> for JsonValue {
+    type Output = JsonValue;
+    fn index(&self, idx: &str) -> &JsonValue {
+	self.find(idx).expect("Can only index objects with &str!")
+    }
+}
+/// `{}` gives the compact serialization, `{:#}` pretty-prints
+/// it with an indentation of two spaces.
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{}", to_pretty_string(self, 2))
+        } else {
+            write!(f, "{}", print_json(self))
+        }
+    }
+}
+
+/// Exact decimal number, stored as an integer mantissa and
+/// the number of digits after the decimal point. Trailing
+/// zeros are removed, so equal values compare equal.
+/// Holds up to 38 significant digits and 38 decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32
+}
+
+impl Decimal {
+    const MAX_SCALE: u32 = 38;
+
+    /// Parses a JSON number literal like `-12.50` or `1.5e-3`.
+    /// Returns None if the value doesn't fit.
+    pub fn parse(s: &str) -> Option<Decimal> {
+        let (number, exp) = match s.find(['e', 'E']) {
+            Some(i) => (&s[..i], s[i + 1..].parse::<i64>().ok()?),
+            None => (s, 0)
+        };
+        let (int, frac) = match number.find('.') {
+            Some(i) => (&number[..i], &number[i + 1..]),
+            None => (number, "")
+        };
+        let (negative, int) = match int.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, int)
+        };
+        let digits = format!("{}{}", int, frac);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut mantissa = digits.parse::<i128>().ok()?;
+        if mantissa == 0 {
+            return Some(Decimal { mantissa: 0, scale: 0 });
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+        // Strip trailing zeros first, so the scale check below
+        // only rejects values that really need more digits.
+        // Both loops run at most 39 times for a non-zero i128.
+        let mut scale = (frac.len() as i64).checked_sub(exp)?;
+        while mantissa % 10 == 0 {
+            mantissa /= 10;
+            scale -= 1;
+        }
+        while scale < 0 {
+            mantissa = mantissa.checked_mul(10)?;
+            scale += 1;
+        }
+        if scale > Decimal::MAX_SCALE as i64 {
+            return None;
+        }
+        Decimal::new(mantissa, scale as u32)
+    }
+
+    // Creates a normalized decimal with trailing zeros removed.
+    fn new(mut mantissa: i128, mut scale: u32) -> Option<Decimal> {
+        if mantissa == 0 {
+            return Some(Decimal { mantissa: 0, scale: 0 });
+        }
+        while scale > 0 && mantissa % 10 == 0 {
+            mantissa /= 10;
+            scale -= 1;
+        }
+        if scale > Decimal::MAX_SCALE {
+            return None;
+        }
+        Some(Decimal { mantissa, scale })
+    }
+
+    /// Exact sum, or None on overflow.
+    pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let scale = std::cmp::max(self.scale, other.scale);
+        let a = self.mantissa.checked_mul(10i128.checked_pow(scale - self.scale)?)?;
+        let b = other.mantissa.checked_mul(10i128.checked_pow(scale - other.scale)?)?;
+        Decimal::new(a.checked_add(b)?, scale)
+    }
+
+    /// Nearest f64 to the decimal.
+    pub fn to_f64(self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+}
+
+impl std::ops::Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: Decimal) -> Decimal {
+        self.checked_add(other).expect("decimal addition overflowed")
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            write!(f, "{}{}", sign, digits)
+        } else if digits.len() <= scale {
+            write!(f, "{}0.{}{}", sign, "0".repeat(scale - digits.len()), digits)
+        } else {
+            let (int, frac) = digits.split_at(digits.len() - scale);
+            write!(f, "{}{}.{}", sign, int, frac)
+        }
+    }
+}
+
+/// How `JsonValue::merge_with` combines two arrays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArrayMergeStrategy {
+    Replace,
+    Concat
+}
+
+/// Builds a JSON object by chaining `key` calls.
+#[derive(Debug, Default)]
+pub struct ObjectBuilder {
+    map: Map
+}
+
+impl ObjectBuilder {
+    pub fn new() -> ObjectBuilder {
+        ObjectBuilder { map: Map::new() }
+    }
+
+    pub fn key(mut self, key: &str, value: JsonValue) -> ObjectBuilder {
+        self.map.insert(key.into(), value);
+        self
+    }
+
+    pub fn build(self) -> JsonValue {
+        Object(self.map)
+    }
+}
+
+/// Builds a JSON array by chaining `push` calls.
+#[derive(Debug, Default)]
+pub struct ArrayBuilder {
+    vec: Vec<JsonValue>
+}
+
+impl ArrayBuilder {
+    pub fn new() -> ArrayBuilder {
+        ArrayBuilder { vec: Vec::new() }
+    }
+
+    pub fn push(mut self, value: JsonValue) -> ArrayBuilder {
+        self.vec.push(value);
+        self
+    }
+
+    pub fn build(self) -> JsonValue {
+        Array(self.vec)
+    }
+}
+
+/// Stores an error code and line/column information
+/// about where the error occurred for better debugging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub reason: ErrorCode,
+    pub line: usize,
+    pub col: usize
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorCode {
+    UnclosedStringLiteral,
+    UnclosedArray,
+    UnclosedObject,
+    MissingColon,
+    ExpectedBool,
+    NumberParsing,
+    ExpectedColon,
+    EndOfFile,
+    ExpectedNull,
+    InvalidControlCharacter,
+    TypeMismatch,
+    InvalidPointer,
+    PathConflict,
+    UnexpectedCharacter,
+    TrailingCharacters,
+    LimitExceeded,
+    InvalidEscape,
+    InvalidQuery,
+    NumberOutOfRange,
+    LengthMismatch,
+    UnclosedComment,
+    InvalidUtf8,
+    Other
+}
+
+impl ErrorCode {
+    pub fn description(&self) -> &str {
+        match *self {           
+            ErrorCode::UnclosedStringLiteral => "Unclosed string literal",
+            ErrorCode::UnclosedArray => "Unclosed array bracket",
+            ErrorCode::UnclosedObject => "Unclosed object bracket",
+            ErrorCode::MissingColon => "Missing colon",
+            ErrorCode::ExpectedBool => "Expected true or false",
+            ErrorCode::NumberParsing => "Error parsing number",
+            ErrorCode::ExpectedColon => "Expected colon",
+            ErrorCode::EndOfFile => "End of file reached",
+            ErrorCode::ExpectedNull => "Expected null",
+            ErrorCode::InvalidControlCharacter => "Unescaped control character in string",
+            ErrorCode::TypeMismatch => "Value has the wrong type",
+            ErrorCode::InvalidPointer => "Invalid JSON Pointer",
+            ErrorCode::PathConflict => "Conflicting paths",
+            ErrorCode::UnexpectedCharacter => "Unexpected character",
+            ErrorCode::TrailingCharacters => "Trailing characters after the value",
+            ErrorCode::LimitExceeded => "Input exceeds the size limit",
+            ErrorCode::InvalidEscape => "Invalid escape sequence in string",
+            ErrorCode::InvalidQuery => "Invalid JSONPath expression",
+            ErrorCode::NumberOutOfRange => "Integer out of range",
+            ErrorCode::LengthMismatch => "Array has the wrong number of elements",
+            ErrorCode::UnclosedComment => "Unclosed block comment",
+            ErrorCode::InvalidUtf8 => "Invalid UTF-8 in input",
+            ErrorCode::Other => "Unknown error"
+        }
+    }
+}
+
+impl JsonError {
+    /// Creates an error that doesn't refer to a position
+    /// in the input, e.g. when converting an already
+    /// parsed value.
+    pub fn new(reason: ErrorCode) -> JsonError {
+        JsonError {
+            reason,
+            line: 0,
+            col: 0
+        }
+    }
+
+    /// Formats the error along with the offending line of
+    /// `source` (the input that was parsed) and a `^` under
+    /// the column. Falls back to the plain message if the
+    /// position isn't inside `source`.
+    pub fn error_with_context(&self, source: &str) -> String {
+        let line = match self.line.checked_sub(1).and_then(|i| source.lines().nth(i)) {
+            Some(line) => line,
+            None => return self.to_string()
+        };
+        // Keep tabs so the caret lines up however they are displayed
+        let padding: String = line.chars()
+            .take(self.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("{}\n{}\n{}^", self, line, padding)
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{} error: {}", self.line, self.col, self.reason.description())
+    }
+}
+
+/// Result of most parsing functions. Either we succeed in parsing
+/// and a value is returned or ther was an error and we return
+/// an error code.
+pub type JsonResult = Result<JsonValue, JsonError>;
+
+/// A (line, col) position in the input.
+pub type Position = (usize, usize);
+
+/// Result of `parse_spanned`: the value along with where it
+/// starts and the position just past its end.
+pub type SpannedResult = Result<(JsonValue, Position, Position), JsonError>;
+
+/// Conversion from a parsed JSON value into a Rust type.
+/// Fails with `TypeMismatch` if the value has the wrong shape.
+pub trait FromJson: Sized {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError>;
+}
+
+impl FromJson for bool {
+    fn from_json(value: &JsonValue) -> Result<bool, JsonError> {
+        match *value {
+            Bool(b) => Ok(b),
+            _ => Err(JsonError::new(TypeMismatch))
+        }
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(value: &JsonValue) -> Result<f64, JsonError> {
+        match *value {
+            Num(_) | U64(_) | RawNumber(_) | Dec(_) => value.as_f64().ok_or_else(|| JsonError::new(TypeMismatch)),
+            _ => Err(JsonError::new(TypeMismatch))
+        }
+    }
+}
+
+// Integers only accept numbers without a fractional part,
+// whole numbers that don't fit into the target type fail with
+// `NumberOutOfRange`. `MAX as f64` rounds up to a power of two
+// for 64-bit types, so the upper bound is exclusive.
+macro_rules! from_json_int {
+    ($($t:ty),*) => {$(
+        impl FromJson for $t {
+            fn from_json(value: &JsonValue) -> Result<$t, JsonError> {
+                // Every number variant is exact as an i64 or a u64
+                // if it is an integer in range at all.
+                let exact: Option<$t> = match value.as_i64_checked() {
+                    Some(n) => n.try_into().ok(),
+                    None => value.as_u64().and_then(|n| n.try_into().ok())
+                };
+                match (exact, value.as_f64()) {
+                    (Some(n), _) => Ok(n),
+                    (None, Some(n)) if n.fract() == 0.0 => Err(JsonError::new(NumberOutOfRange)),
+                    _ => Err(JsonError::new(TypeMismatch))
+                }
+            }
+        }
+    )*}
+}
+
+from_json_int!(i32, i64, u32, u64, usize);
+
+impl FromJson for String {
+    fn from_json(value: &JsonValue) -> Result<String, JsonError> {
+        match *value {
+            Str(_) | SharedStr(_) => Ok(value.as_str().unwrap().to_string()),
+            _ => Err(JsonError::new(TypeMismatch))
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(value: &JsonValue) -> Result<Vec<T>, JsonError> {
+        match *value {
+            Array(ref values) => values.iter().map(T::from_json).collect(),
+            _ => Err(JsonError::new(TypeMismatch))
+        }
+    }
+}
+
+/// Fails with `LengthMismatch` unless the array has exactly
+/// `N` elements.
+impl<T: FromJson, const N: usize> FromJson for [T; N] {
+    fn from_json(value: &JsonValue) -> Result<[T; N], JsonError> {
+        let values = Vec::<T>::from_json(value)?;
+        values.try_into().map_err(|_| JsonError::new(LengthMismatch))
+    }
+}
+
+// Tuples are read from arrays with one element per field,
+// e.g. `(f64, String)` from `[1.5, "x"]`.
+macro_rules! from_json_tuple {
+    ($len:expr => $($t:ident $i:tt),*) => {
+        impl<$($t: FromJson),*> FromJson for ($($t,)*) {
+            fn from_json(value: &JsonValue) -> Result<($($t,)*), JsonError> {
+                match *value {
+                    Array(ref values) if values.len() == $len => Ok(($($t::from_json(&values[$i])?,)*)),
+                    Array(_) => Err(JsonError::new(LengthMismatch)),
+                    _ => Err(JsonError::new(TypeMismatch))
+                }
+            }
+        }
+    }
+}
+
+from_json_tuple!(1 => A 0);
+from_json_tuple!(2 => A 0, B 1);
+from_json_tuple!(3 => A 0, B 1, C 2);
+from_json_tuple!(4 => A 0, B 1, C 2, D 3);
+
+/// `null` maps to `None`, anything else is converted to `T`.
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(value: &JsonValue) -> Result<Option<T>, JsonError> {
+        match *value {
+            Null => Ok(None),
+            ref v => T::from_json(v).map(Some)
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for HashMap<String, T> {
+    fn from_json(value: &JsonValue) -> Result<HashMap<String, T>, JsonError> {
+        match *value {
+            Object(ref map) => map.iter()
+                .map(|(k, v)| T::from_json(v).map(|v| (k.to_string(), v)))
+                .collect(),
+            _ => Err(JsonError::new(TypeMismatch))
+        }
+    }
+}
+
+/// Converts into a `serde_json::Value`. Integral numbers become
+/// integers, NaN and infinities become `Null` since serde_json
+/// can't represent them.
+#[cfg(feature = "serde_json")]
+impl From<JsonValue> for serde_json::Value {
+    fn from(value: JsonValue) -> serde_json::Value {
+        use serde_json::{Number, Value};
+        match value {
+            Null => Value::Null,
+            Bool(b) => Value::Bool(b),
+            Num(n) if n.fract() == 0.0 && n.abs() < 9.2e18 => Value::Number(Number::from(n as i64)),
+            Num(n) => Number::from_f64(n).map_or(Value::Null, Value::Number),
+            U64(n) => Value::Number(Number::from(n)),
+            RawNumber(s) => s.parse::<Number>().map_or(Value::Null, Value::Number),
+            Dec(d) => d.to_string().parse::<Number>().map_or(Value::Null, Value::Number),
+            Str(s) => Value::String(s),
+            SharedStr(s) => Value::String(s.to_string()),
+            Array(values) => Value::Array(values.into_iter().map(Value::from).collect()),
+            Object(map) => Value::Object(map.into_iter().map(|(k, v)| (k.to_string(), Value::from(v))).collect())
+        }
+    }
+}
+
+/// Converts from a `serde_json::Value`. Integers above `i64::MAX`
+/// are kept exactly in `U64`, all other numbers become `Num`.
+#[cfg(feature = "serde_json")]
+impl From<serde_json::Value> for JsonValue {
+    fn from(value: serde_json::Value) -> JsonValue {
+        use serde_json::Value;
+        match value {
+            Value::Null => Null,
+            Value::Bool(b) => Bool(b),
+            Value::Number(n) => match n.as_u64() {
+                Some(u) if u > i64::MAX as u64 => U64(u),
+                _ => Num(n.as_f64().unwrap_or(0.0))
+            },
+            Value::String(s) => Str(s),
+            Value::Array(values) => Array(values.into_iter().map(JsonValue::from).collect()),
+            Value::Object(map) => Object(map.into_iter().map(|(k, v)| (k.into(), JsonValue::from(v))).collect())
+        }
+    }
+}
+
+/// Iterator decoding a UTF-8 byte slice into chars for the
+/// char-based parser, so a byte buffer doesn't have to be
+/// validated as a whole `str` before parsing starts. ASCII
+/// bytes are passed through directly; multi-byte sequences
+/// are validated as they are reached. Decoding stops at the
+/// first invalid sequence, which a parser created with
+/// `JsonParser::from_bytes` reports as `InvalidUtf8`.
+pub struct ByteChars<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    invalid: bool
+}
+
+impl<'a> ByteChars<'a> {
+    pub fn new(bytes: &'a [u8]) -> ByteChars<'a> {
+        ByteChars { bytes, pos: 0, invalid: false }
+    }
+}
+
+impl<'a> Iterator for ByteChars<'a> {
+    type Item = char;
+
+    #[inline]
+    fn next(&mut self) -> Option<char> {
+        if self.invalid {
+            return None;
+        }
+        let b = *self.bytes.get(self.pos)?;
+        if b < 0x80 {
+            self.pos += 1;
+            return Some(b as char);
+        }
+        let width = match b {
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => 1
+        };
+        let end = std::cmp::min(self.pos + width, self.bytes.len());
+        match str::from_utf8(&self.bytes[self.pos..end]) {
+            Ok(s) => {
+                self.pos = end;
+                s.chars().next()
+            },
+            Err(_) => {
+                self.invalid = true;
+                None
+            }
+        }
+    }
+}
+
+/// Iterator over the chars of an owned `String`, so a parser
+/// can own its input instead of borrowing it.
+pub struct OwnedChars {
+    s: String,
+    pos: usize
+}
+
+impl OwnedChars {
+    pub fn new(s: String) -> OwnedChars {
+        OwnedChars { s, pos: 0 }
+    }
+}
+
+impl Iterator for OwnedChars {
+    type Item = char;
+
+    #[inline]
+    fn next(&mut self) -> Option<char> {
+        let c = self.s[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+}
+
+/// Iterator decoding UTF-8 from a reader as it goes, so only
+/// a small buffer of the input is held in memory at a time.
+/// Reading stops at the first I/O error, which is kept in
+/// `error`, and at the first invalid UTF-8 sequence, which a
+/// parser created with `JsonParser::from_reader` reports as
+/// `InvalidUtf8`.
+pub struct ReaderChars<R> {
+    bytes: io::Bytes<io::BufReader<R>>,
+    bytes_read: usize,
+    error: Option<io::Error>,
+    invalid: bool
+}
+
+impl<R: Read> ReaderChars<R> {
+    pub fn new(reader: R) -> ReaderChars<R> {
+        ReaderChars {
+            bytes: io::BufReader::new(reader).bytes(),
+            bytes_read: 0,
+            error: None,
+            invalid: false
+        }
+    }
+
+    // Returns the next byte, or None at the end of the input
+    // or on an I/O error.
+    fn next_byte(&mut self) -> Option<u8> {
+        match self.bytes.next()? {
+            Ok(b) => {
+                self.bytes_read += 1;
+                Some(b)
+            },
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for ReaderChars<R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.invalid {
+            return None;
+        }
+        let b = self.next_byte()?;
+        if b < 0x80 {
+            return Some(b as char);
+        }
+        let width = match b {
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => 1
+        };
+        let mut buf = [b, 0, 0, 0];
+        for slot in buf.iter_mut().take(width).skip(1) {
+            match self.next_byte() {
+                Some(c) => *slot = c,
+                None => break
+            }
+        }
+        match str::from_utf8(&buf[..width]) {
+            Ok(s) => s.chars().next(),
+            Err(_) => {
+                self.invalid = true;
+                None
+            }
+        }
+    }
+}
+
+/// Options controlling how closely the parser follows the
+/// JSON spec. With all options at their defaults the parser
+/// behaves as it did before the options existed; each flag
+/// opts into an extension or, like
+/// `reject_control_characters`, into stricter checking.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ParserOptions {
+    /// Reject unescaped control characters (0x00-0x1F)
+    /// inside string literals.
+    pub reject_control_characters: bool,
+    /// Accept `true`, `false` and `null` in any case,
+    /// e.g. `TRUE` or `Null`.
+    pub case_insensitive_literals: bool,
+    /// Accept `_` separators between the digits of a
+    /// number, e.g. `1_000_000`.
+    pub allow_number_underscores: bool,
+    /// Skip array elements that fail to parse instead of
+    /// failing the whole parse. The skipped errors are
+    /// available through `JsonParser::errors`.
+    pub recover_array_errors: bool,
+    /// Keep numbers as their literal text in `RawNumber`
+    /// instead of converting them, so no precision is lost.
+    pub raw_numbers: bool,
+    /// Characters treated as whitespace in addition to the
+    /// four JSON ones, e.g. `'\x0b'` (vertical tab) or
+    /// `'\x0c'` (form feed).
+    pub extra_whitespace: Vec<char>,
+    /// Accept JavaScript-style `'single quoted'` strings, with
+    /// the same escapes as double-quoted ones plus `\'`.
+    pub allow_single_quotes: bool,
+    /// Accept JavaScript-style bare identifier keys matching
+    /// `[A-Za-z_][A-Za-z0-9_]*`, e.g. `{name: 1}`.
+    pub allow_unquoted_keys: bool,
+    /// Parse numbers into exact `Dec` decimals, e.g. for
+    /// money amounts, instead of f64.
+    pub decimal_numbers: bool,
+    /// Skip `// line` and `/* block */` comments wherever
+    /// whitespace is allowed.
+    pub allow_comments: bool,
+    /// Accept a comma after the last array element or
+    /// object member, e.g. `[1, 2,]`.
+    pub allow_trailing_commas: bool,
+    /// Fail with `NumberOutOfRange` on integer literals outside
+    /// the i64 and u64 ranges instead of silently converting
+    /// them to an imprecise f64.
+    pub strict_integers: bool,
+    /// Skip a first line starting with `#!`, as found in
+    /// executable config files.
+    pub skip_shebang: bool,
+    /// Treat a missing object member value, as in
+    /// `{"a":,"b":1}`, as `null`.
+    pub allow_implicit_null: bool,
+    /// Accept the `NaN`, `Infinity` and `-Infinity` literals
+    /// written e.g. by Python's `json` module.
+    pub allow_nan_infinity: bool
+}
+
+impl ParserOptions {
+    /// Options for human-written config files in a subset of
+    /// JSON5: comments, trailing commas, single-quoted strings
+    /// and unquoted keys are all accepted.
+    pub fn json5_lite() -> ParserOptions {
+        ParserOptions {
+            allow_comments: true,
+            allow_trailing_commas: true,
+            allow_single_quotes: true,
+            allow_unquoted_keys: true,
+            ..ParserOptions::default()
+        }
+    }
+}
+
+/// The parser stores an iterator over characters,
+/// information about the current position (line/col)
+/// and the current character. `S` is the scratch buffer
+/// string literals are read into, borrowed from the caller
+/// by parsers created with `with_scratch`.
+pub struct JsonParser<T, S = String> {
+    iter: T,
+    line: usize,
+    col: usize,
+    ch: Option<char>,
+    // Position just past the last consumed non-whitespace character
+    value_end: Position,
+    // When set, arrays and objects are checked but their
+    // contents are thrown away instead of building a tree
+    discard: bool,
+    // Errors skipped over in recovery mode
+    errors: Vec<JsonError>,
+    // Number of currently open arrays and objects
+    depth: usize,
+    // When set, a container that fails to parse leaves what
+    // it parsed so far in `partial`
+    keep_partial: bool,
+    partial: Option<JsonValue>,
+    // Quote of the string literal being read, still set if
+    // the string had an error
+    in_string: Option<char>,
+    // A malformed comment ends the input early; the error is
+    // reported in place of the parse result
+    comment_error: Option<JsonError>,
+    // Tells whether a decoding input stopped early at invalid
+    // UTF-8, which is then reported in place of the parse result
+    utf8_error: Option<fn(&T) -> bool>,
+    options: ParserOptions,
+    // Buffer that string literals are read into, kept between
+    // strings so its capacity is reused
+    scratch: Option<S>
+}
+
+impl<T: Iterator<Item = char>> JsonParser<T> {
+    pub fn new(input: T) -> JsonParser<T> {
+        JsonParser::with_options(input, ParserOptions::default())
+    }
+
+    pub fn with_options(input: T, options: ParserOptions) -> JsonParser<T> {
+        JsonParser::build(input, options, None)
+    }
+}
+
+impl<'s, T: Iterator<Item = char>> JsonParser<T, &'s mut String> {
+    /// Creates a parser that reads string literals into the
+    /// `scratch` buffer and copies each out with one exactly sized
+    /// allocation, instead of growing a new `String` for each one.
+    /// The buffer keeps its capacity for the next parser, e.g.
+    /// in a hot loop.
+    pub fn with_scratch(input: T, options: ParserOptions, scratch: &'s mut String) -> JsonParser<T, &'s mut String> {
+        JsonParser::build(input, options, Some(scratch))
+    }
+}
+
+impl<T: Iterator<Item = char>, S: BorrowMut<String>> JsonParser<T, S> {
+    fn build(input: T, options: ParserOptions, scratch: Option<S>) -> JsonParser<T, S> {
+        let mut parser = JsonParser {
+            iter: input,
+            line: 1,
+            col: 0,
+            ch: Some('\x00'),
+            value_end: (1, 1),
+            discard: false,
+            errors: Vec::new(),
+            depth: 0,
+            keep_partial: false,
+            partial: None,
+            in_string: None,
+            comment_error: None,
+            utf8_error: None,
+            options,
+            scratch
+        };
+        parser.consume_char();
+        // Skip a leading byte order mark, some editors add one.
+        if parser.ch_is('\u{feff}') {
+            parser.consume_char();
+            parser.col = 1;
+        }
+        if parser.options.skip_shebang && parser.ch_is('#') {
+            parser.skip_shebang();
+        }
+        parser
+    }
+
+    // Skips the rest of the first line if it starts with `#!`.
+    fn skip_shebang(&mut self) {
+        let col = self.col;
+        self.consume_char();
+        if self.ch_is('!') {
+            while !self.eof() && !self.ch_is('\n') {
+                self.consume_char();
+            }
+        } else {
+            // A lone `#` is never valid JSON, so parsing fails right
+            // here and the character after it is never looked at.
+            self.ch = Some('#');
+            self.col = col;
+        }
+    }
+
+    fn error<V>(&self, reason: ErrorCode) -> Result<V, JsonError> {
+        self.error_at(reason, self.line, self.col)
+    }
+
+    fn error_at<V>(&self, reason: ErrorCode, line: usize, col: usize) -> Result<V, JsonError> {
+        Err(JsonError {
+            reason,
+            line,
+            col
+        })
+    }
+
+    // Returns the error, first keeping the container parsed
+    // so far if partial trees are wanted.
+    fn partial_error(&mut self, container: JsonValue, error: JsonResult) -> JsonResult {
+        if self.keep_partial {
+            self.partial = Some(container);
+        }
+        error
+    }
+
+    // Advances the character iterator by one and returns the new character
+    #[inline]
+    fn consume_char(&mut self) -> char {
+        if !self.ch_is_whitespace() {
+            self.value_end = (self.line, self.col + 1);
+        }
+        // The character after a newline starts the next line
+        if self.ch_is('\n') {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        self.ch = self.iter.next();
+        self.ch.unwrap_or('\x00')
+    }
+
+    // Is the current character equal to c?
+    #[inline]
+    fn ch_is(&self, c: char) -> bool {
+        self.ch == Some(c)
+    }
+
+    // Are we at the end of the file?
+    #[inline]
+    fn eof(&self) -> bool {
+        self.ch.is_none()
+    }
+
+    // Advances the input by the length of the passed text.
+    // If one of the characters in the input is not equal
+    // to the corresponding character in the text, returns None.
+    fn consume_text(&mut self, text: &str) -> Option<()> {
+        self.consume_whitespace();
+
+        for c in text.chars() {
+            if !self.ch_is(c) {
+                return None;
+            }
+            self.consume_char();
+        }
+
+        Some(())
+    }
+
+    // Like consume_text, but compares ASCII characters
+    // without regard to case.
+    fn consume_text_ignore_case(&mut self, text: &str) -> Option<()> {
+        self.consume_whitespace();
+
+        for c in text.chars() {
+            if !self.ch_is_ignore_case(c) {
+                return None;
+            }
+            self.consume_char();
+        }
+
+        Some(())
+    }
+
+    // Consumes one of the literals true, false or null,
+    // respecting the case sensitivity option. The literal
+    // has to end there, e.g. `truex` is not `true`.
+    fn consume_literal(&mut self, text: &str) -> Option<()> {
+        if self.options.case_insensitive_literals {
+            self.consume_text_ignore_case(text)?
+        } else {
+            self.consume_text(text)?
+        }
+        if self.ch.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+        self.consume_whitespace();
+        Some(())
+    }
+
+    // Is the current character equal to c, ignoring case
+    // if literals are case insensitive?
+    #[inline]
+    fn literal_ch_is(&self, c: char) -> bool {
+        if self.options.case_insensitive_literals {
+            self.ch_is_ignore_case(c)
+        } else {
+            self.ch_is(c)
+        }
+    }
+
+    #[inline]
+    fn ch_is_ignore_case(&self, c: char) -> bool {
+        self.ch.is_some_and(|d| d.eq_ignore_ascii_case(&c))
+    }
+
+    #[inline]
+    fn ch_is_digit(&self) -> bool {
+        matches!(self.ch, Some('0'..='9'))
+    }
+
+    #[inline]
+    fn ch_is_control(&self) -> bool {
+        matches!(self.ch, Some('\x00'..='\x1f'))
+    }
+
+    #[inline]
+    fn ch_is_whitespace(&self) -> bool {
+        self.ch_is(' ') || self.ch_is('\n') ||
+            self.ch_is('\t') || self.ch_is('\r') ||
+            self.ch.is_some_and(|c| self.options.extra_whitespace.contains(&c))
+    }
+
+    // Consumes whitespace until the next non-whitespace character is reached
+    #[inline]
+    fn consume_whitespace(&mut self) {
+        loop {
+            while self.ch_is_whitespace() {
+                self.consume_char();
+            }
+            if !(self.options.allow_comments && self.ch_is('/')) {
+                return;
+            }
+            // Comments don't extend the span of the value before them.
+            let value_end = self.value_end;
+            self.consume_comment();
+            self.value_end = value_end;
+        }
+    }
+
+    // Skips a `//` or `/* */` comment starting at the current `/`.
+    // A `/` starting neither, or a block comment without its
+    // closing `*/`, stops the input and sets `comment_error`.
+    fn consume_comment(&mut self) {
+        let (line, col) = (self.line, self.col);
+        self.consume_char();
+        if self.ch_is('/') {
+            while !self.eof() && !self.ch_is('\n') {
+                self.consume_char();
+            }
+        } else if self.ch_is('*') {
+            self.consume_char();
+            loop {
+                match self.ch {
+                    None => {
+                        self.comment_error = Some(JsonError { reason: UnclosedComment, line, col });
+                        return;
+                    },
+                    Some('*') => {
+                        self.consume_char();
+                        if self.ch_is('/') {
+                            self.consume_char();
+                            return;
+                        }
+                    },
+                    Some(_) => { self.consume_char(); }
+                }
+            }
+        } else {
+            self.comment_error = Some(JsonError { reason: UnexpectedCharacter, line, col });
+            self.ch = None;
+        }
+    }
+
+    // Consumes a numerical literal and returns its value as a string.
+    // Stops on the first character that can't be part of a number,
+    // leaving it as the current character, or at the end of input.
+    // A sign is only part of the number at the very start or right
+    // after the exponent marker, so `1-2` stops before the `-`.
+    #[inline]
+    fn consume_num(&mut self) -> String {
+        let mut result = String::new();
+        self.consume_num_into(&mut result);
+        result
+    }
+
+    // Like consume_num, but appends the number to `buf`.
+    fn consume_num_into(&mut self, buf: &mut String) {
+        let start = buf.len();
+        self.consume_whitespace();
+
+        while let Some(c) = self.ch {
+            let is_num_char = match c {
+                '0'..='9' | '.' | 'e' | 'E' => true,
+                '-' | '+' => buf.len() == start || buf.ends_with(['e', 'E']),
+                '_' => self.options.allow_number_underscores,
+                _ => false
+            };
+            if !is_num_char {
+                break;
+            }
+            buf.push(c);
+            self.consume_char();
+        }
+    }
+
+    // Parses the JSON null value.
+    fn parse_null(&mut self) -> JsonResult {
+        match self.consume_literal("null") {
+            Some(_) => Ok(Null),
+            None => self.error(ExpectedNull)
+        }
+    }
+
+    // Parses the `NaN` and `Infinity` extensions. With
+    // case-insensitive literals a leading `N` may also
+    // start a `null`.
+    fn parse_non_finite(&mut self) -> JsonResult {
+        let value = if self.ch_is('I') {
+            self.consume_literal("Infinity").map(|_| f64::INFINITY)
+        } else {
+            self.consume_char();
+            if !self.ch_is('a') {
+                if self.options.case_insensitive_literals && self.consume_literal("ull").is_some() {
+                    return Ok(Null);
+                }
+                return self.error(ExpectedNull);
+            }
+            self.consume_literal("aN").map(|_| f64::NAN)
+        };
+        match value {
+            Some(n) => Ok(Num(n)),
+            None => self.error(NumberParsing)
+        }
+    }
+
+    // Parses a JSON number.
+    fn parse_num(&mut self) -> JsonResult {
+        self.consume_whitespace();
+        
+        if self.ch_is_digit() || self.ch_is('-') {
+            let mut num_str = self.consume_num();
+
+            if num_str == "-" && self.options.allow_nan_infinity && self.ch_is('I') {
+                return match self.consume_literal("Infinity") {
+                    Some(_) => Ok(Num(f64::NEG_INFINITY)),
+                    None => self.error(NumberParsing)
+                };
+            }
+
+            if num_str.contains('_') {
+                match strip_underscores(&num_str) {
+                    Some(stripped) => num_str = stripped,
+                    None => return self.error(NumberParsing)
+                }
+            }
+
+            // Raw numbers are written back out verbatim, so they
+            // have to follow the JSON grammar exactly.
+            if self.options.raw_numbers {
+                return if is_json_number(&num_str) {
+                    Ok(RawNumber(num_str))
+                } else {
+                    self.error(NumberParsing)
+                };
+            }
+
+            if self.options.decimal_numbers {
+                // Check the syntax with the float parser first.
+                return match num_str.parse::<f64>().ok().and(Decimal::parse(&num_str)) {
+                    Some(d) => Ok(Dec(d)),
+                    None => self.error(NumberParsing)
+                };
+            }
+
+            let digits = num_str.trim_start_matches('-');
+            let is_integer = !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit());
+            if self.options.strict_integers && is_integer &&
+                num_str.parse::<i64>().is_err() && num_str.parse::<u64>().is_err() {
+                return self.error(NumberOutOfRange);
+            }
+
+            // Integers beyond the i64 range are kept exactly
+            // as long as they still fit into a u64.
+            if num_str.chars().all(|c| c.is_ascii_digit()) {
+                if let Ok(n) = num_str.parse::<u64>() {
+                    if n > i64::MAX as u64 {
+                        return Ok(U64(n));
+                    }
+                }
+            }
+            
+            let n = num_str.parse::<f64>();
+            match n {
+                Ok(num) => Ok(Num(num)),
+                Err(_) => self.error(NumberParsing)
+            }
+        } else {
+            self.error(NumberParsing)
+        }
+    }
+    
+    // Parses a JSON string value.
+    fn parse_string(&mut self) -> JsonResult {
+        self.consume_whitespace();
+        
+        if self.ch_is('"') || (self.options.allow_single_quotes && self.ch_is('\'')) {
+            let quote = self.ch.unwrap();
+            self.consume_char();
+            // The buffer is moved out while reading, since reading
+            // needs the whole parser mutably
+            match self.scratch.take() {
+                Some(mut buffer) => {
+                    let result = {
+                        let s = buffer.borrow_mut();
+                        s.clear();
+                        self.read_string_contents(quote, s).map(|_| Str(s.as_str().to_owned()))
+                    };
+                    self.scratch = Some(buffer);
+                    result
+                },
+                None => {
+                    let mut s = String::new();
+                    self.read_string_contents(quote, &mut s).map(|_| Str(s))
+                }
+            }
+        }
+        else {
+            self.error(UnclosedStringLiteral)
+        }
+    }
+
+    // Reads the rest of a string literal after the opening quote
+    // into `s`, consuming the closing quote.
+    fn read_string_contents(&mut self, quote: char, s: &mut String) -> Result<(), JsonError> {
+        self.in_string = Some(quote);
+        while !self.eof() {
+            if self.ch_is(quote) {
+                self.consume_char();
+                self.in_string = None;
+                return Ok(());
+            }
+            if self.options.reject_control_characters && self.ch_is_control() {
+                return self.error(InvalidControlCharacter);
+            }
+            if self.ch_is('\\') {
+                self.consume_char();
+                match self.parse_escape() {
+                    Some(c) => s.push(c),
+                    None => return self.error(InvalidEscape)
+                }
+                continue;
+            }
+            s.push(self.ch.unwrap());
+            self.consume_char();
+        }
+        self.error(UnclosedStringLiteral)
+    }
+
+    // Parses the escape sequence after a backslash, e.g. `n`
+    // or `u00e9`. Surrogate pairs written as two `\u` escapes
+    // are combined; a lone surrogate is an error.
+    fn parse_escape(&mut self) -> Option<char> {
+        let c = match self.ch? {
+            '"' => '"',
+            '\'' if self.options.allow_single_quotes => '\'',
+            '\\' => '\\',
+            '/' => '/',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                self.consume_char();
+                let high = self.parse_hex4()?;
+                if !(0xd800..0xdc00).contains(&high) {
+                    return char::from_u32(high);
+                }
+                if !self.ch_is('\\') {
+                    return None;
+                }
+                self.consume_char();
+                if !self.ch_is('u') {
+                    return None;
+                }
+                self.consume_char();
+                let low = self.parse_hex4()?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    return None;
+                }
+                return char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00));
+            },
+            _ => return None
+        };
+        self.consume_char();
+        Some(c)
+    }
+
+    // Reads the four hex digits of a `\u` escape.
+    fn parse_hex4(&mut self) -> Option<u32> {
+        let mut n = 0;
+        for _ in 0..4 {
+            n = n * 16 + self.ch?.to_digit(16)?;
+            self.consume_char();
+        }
+        Some(n)
+    }
+
+    // Consumes an identifier like `name_2`, for unquoted keys.
+    fn consume_identifier(&mut self) -> String {
+        let mut ident = String::new();
+        while let Some(c) = self.ch {
+            if !c.is_ascii_alphanumeric() && c != '_' {
+                break;
+            }
+            ident.push(c);
+            self.consume_char();
+        }
+        ident
+    }
+
+    // Parses a JSON boolean.
+    fn parse_bool(&mut self) -> JsonResult {
+        self.consume_whitespace();
+        
+        let (text, value) = if self.literal_ch_is('f') {
+            ("false", false)
+        } else if self.literal_ch_is('t') {
+            ("true", true)
+        } else {
+            return self.error(ExpectedBool);
+        };
+        match self.consume_literal(text) {
+            Some(_) => Ok(Bool(value)),
+            None => self.error(ExpectedBool)
+        }
+    }
+    // Parses any JSON value, this is the entry point
+    // for the parser. The current character decides
+    // which kind of value has to follow.
+    fn parse_value(&mut self) -> JsonResult {
+        self.consume_whitespace();
+        match self.ch {
+            Some('t') | Some('f') | Some('T') | Some('F') => self.parse_bool(),
+            Some('"') => self.parse_string(),
+            Some('\'') if self.options.allow_single_quotes => self.parse_string(),
+            Some('I') | Some('N') if self.options.allow_nan_infinity => self.parse_non_finite(),
+            Some('n') | Some('N') => self.parse_null(),
+            Some('[') => self.parse_array(),
+            Some('{') => self.parse_object(),
+            Some('-') | Some('0'..='9') => self.parse_num(),
+            Some(_) => self.error(UnexpectedCharacter),
+            None => self.error(EndOfFile)
+        }
+    }
+    
+    // Parses a JSON array of values. Example: [true, false, 1, "hello"]
+    fn parse_array(&mut self) -> JsonResult {
+        if self.ch_is('[') {
+            // Remember where the array started for error reporting
+            let (line, col) = (self.line, self.col);
+            // Consume the opening bracket
+            self.consume_char();
+            self.depth += 1;
+            let depth = self.depth;
+            let mut array = Vec::new();
+            let mut after_comma = false;
+
+            self.consume_whitespace();
+            if self.ch_is(']') {
+                self.consume_char();
+                self.depth -= 1;
+                return Ok(Array(array));
+            }
+            loop {
+                self.consume_whitespace();
+                if self.eof() {
+                    return self.partial_error(Array(array), self.error_at(UnclosedArray, line, col));
+                }
+                if after_comma && self.options.allow_trailing_commas && self.ch_is(']') {
+                    self.consume_char();
+                    self.depth -= 1;
+                    return Ok(Array(array));
+                }
+                let value = self.parse_value();
+                match value {
+                    Ok(_) if self.discard => {},
+                    Ok(v) => array.push(v),
+                    Err(e) if self.options.recover_array_errors => {
+                        self.errors.push(e);
+                        self.partial = None;
+                        // The error may come from inside a string or
+                        // nested containers
+                        if let Some(quote) = self.in_string.take() {
+                            self.skip_string_contents(quote);
+                            self.consume_char();
+                        }
+                        let nested = self.depth - depth;
+                        self.skip_array_element(nested);
+                        self.depth = depth;
+                        if self.eof() {
+                            return self.partial_error(Array(array), self.error_at(UnclosedArray, line, col));
+                        }
+                    },
+                    Err(e) => {
+                        // Keep the part of the element that was parsed.
+                        if let Some(child) = self.partial.take() {
+                            array.push(child);
+                        }
+                        return self.partial_error(Array(array), Err(e));
+                    }
+                }
+                self.consume_whitespace();
+                // Parse the next value in the array
+                after_comma = self.ch_is(',');
+                if after_comma {
+                    self.consume_char();
+                    continue;
+                }
+                // Reached the end of the array, return it
+                if self.ch_is(']') {
+                    self.consume_char();
+                    self.depth -= 1;
+                    return Ok(Array(array));
+                }
+                // Elements must be separated by commas
+                if !self.eof() {
+                    return self.partial_error(Array(array), self.error(UnexpectedCharacter));
+                }
+            }
+        }
+        else {
+            self.error(UnclosedArray)
+        }
+    }
+
+    // Skips ahead to the closing `quote` of a string literal,
+    // stepping over escaped characters.
+    fn skip_string_contents(&mut self, quote: char) {
+        while !self.eof() && !self.ch_is(quote) {
+            if self.ch_is('\\') {
+                self.consume_char();
+            }
+            self.consume_char();
+        }
+    }
+
+    // Skips ahead to the `,` or `]` that ends the current array
+    // element, stepping over nested containers and strings.
+    // `depth` is the number of containers still open inside
+    // the element.
+    fn skip_array_element(&mut self, mut depth: usize) {
+        while let Some(c) = self.ch {
+            match c {
+                ',' | ']' if depth == 0 => return,
+                '[' | '{' => depth += 1,
+                ']' | '}' if depth > 0 => depth -= 1,
+                '"' | '\'' if c == '"' || self.options.allow_single_quotes => {
+                    self.consume_char();
+                    self.skip_string_contents(c);
+                },
+                _ => {}
+            }
+            self.consume_char();
+        }
+    }
+
+    // Parses a JSON object. Example: {"key": [1, 2, 3]}
+    fn parse_object(&mut self) -> JsonResult {
+        if self.eof() {
+            return self.error(EndOfFile);
+        }
+        self.consume_whitespace();
+        if self.ch_is('{') {
+            // Remember where the object started for error reporting
+            let (line, col) = (self.line, self.col);
+            let mut object = Map::new();
+            let mut after_comma = false;
+            self.consume_char();
+            self.depth += 1;
+            self.consume_whitespace();
+            if self.ch_is('}') {
+                self.consume_char();
+                self.depth -= 1;
+                return Ok(Object(object));
+            }
+            loop {
+                self.consume_whitespace();
+                if self.eof() {
+                    return self.partial_error(Object(object), self.error_at(UnclosedObject, line, col));
+                }
+                if after_comma && self.options.allow_trailing_commas && self.ch_is('}') {
+                    self.consume_char();
+                    self.depth -= 1;
+                    return Ok(Object(object));
+                }
+                // The key is a string value, or a bare identifier
+                // if unquoted keys are allowed.
+                let key = match self.ch {
+                    Some(c) if self.options.allow_unquoted_keys && (c.is_ascii_alphabetic() || c == '_') =>
+                        Ok(Str(self.consume_identifier())),
+                    _ => self.parse_string()
+                };
+                let key_string = match key {
+                    Ok(s) => s.into_string().unwrap(),
+                    e @ Err(_) => return self.partial_error(Object(object), e)
+                };
+
+                self.consume_whitespace();
+                if self.eof() {
+                    return self.partial_error(Object(object), self.error_at(UnclosedObject, line, col));
+                }
+
+                // The separating colon between key and value
+                if !self.ch_is(':') {
+                    return self.partial_error(Object(object), self.error(ExpectedColon));
+                }
+                self.consume_char();
+                self.consume_whitespace();
+                if self.eof() {
+                    return self.partial_error(Object(object), self.error_at(UnclosedObject, line, col));
+                }
+
+                // Parse any value, an empty one is null if allowed
+                let value = if self.options.allow_implicit_null && (self.ch_is(',') || self.ch_is('}')) {
+                    Ok(Null)
+                } else {
+                    self.parse_value()
+                };
+                match value {
+                    Ok(_) if self.discard => {},
+                    Ok(v) => { object.insert(key_string.into(), v); },
+                    Err(e) => {
+                        if let Some(child) = self.partial.take() {
+                            object.insert(key_string.into(), child);
+                        }
+                        return self.partial_error(Object(object), Err(e));
+                    }
+                }
+                self.consume_whitespace();
+
+                // Continue with the next value
+                after_comma = self.ch_is(',');
+                if after_comma {
+                    self.consume_char();
+                    continue;
+                }
+                // End of the current object
+                if self.ch_is('}') {
+                    self.consume_char();
+                    self.depth -= 1;
+                    return Ok(Object(object));
+                }
+                // Members must be separated by commas
+                if !self.eof() {
+                    return self.partial_error(Object(object), self.error(UnexpectedCharacter));
+                }
+            }
+        }
+        
+        else {
+            self.error(UnclosedObject)
+        }
+        
+    }
+
+    // Parses a value into the arena and returns its node index.
+    // Containers collect their children on the arena's scratch
+    // stacks and move them into one contiguous range when done.
+    fn parse_arena_value(&mut self, arena: &mut Arena) -> Result<usize, JsonError> {
+        self.consume_whitespace();
+        let (line, col) = (self.line, self.col);
+        let node = match self.ch {
+            Some('[') => {
+                self.consume_char();
+                self.consume_whitespace();
+                let mark = arena.item_stack.len();
+                if self.ch_is(']') {
+                    self.consume_char();
+                } else {
+                    loop {
+                        self.consume_whitespace();
+                        if self.eof() {
+                            return self.error_at(UnclosedArray, line, col);
+                        }
+                        let id = self.parse_arena_value(arena)?;
+                        arena.item_stack.push(id);
+                        self.consume_whitespace();
+                        if self.ch_is(',') {
+                            self.consume_char();
+                        } else if self.ch_is(']') {
+                            self.consume_char();
+                            break;
+                        } else if self.eof() {
+                            return self.error_at(UnclosedArray, line, col);
+                        } else {
+                            return self.error(UnexpectedCharacter);
+                        }
+                    }
+                }
+                let start = arena.items.len();
+                arena.items.extend(arena.item_stack.drain(mark..));
+                ArenaNode::Array(start, arena.items.len())
+            },
+            Some('{') => {
+                self.consume_char();
+                self.consume_whitespace();
+                let mark = arena.member_stack.len();
+                if self.ch_is('}') {
+                    self.consume_char();
+                } else {
+                    loop {
+                        self.consume_whitespace();
+                        if self.eof() {
+                            return self.error_at(UnclosedObject, line, col);
+                        }
+                        let key = self.parse_arena_string(arena)?;
+                        self.consume_whitespace();
+                        if self.eof() {
+                            return self.error_at(UnclosedObject, line, col);
+                        }
+                        if !self.ch_is(':') {
+                            return self.error(ExpectedColon);
+                        }
+                        self.consume_char();
+                        self.consume_whitespace();
+                        if self.eof() {
+                            return self.error_at(UnclosedObject, line, col);
+                        }
+                        let id = self.parse_arena_value(arena)?;
+                        arena.member_stack.push((key, id));
+                        self.consume_whitespace();
+                        if self.ch_is(',') {
+                            self.consume_char();
+                        } else if self.ch_is('}') {
+                            self.consume_char();
+                            break;
+                        } else if self.eof() {
+                            return self.error_at(UnclosedObject, line, col);
+                        } else {
+                            return self.error(UnexpectedCharacter);
+                        }
+                    }
+                }
+                let start = arena.members.len();
+                arena.members.extend(arena.member_stack.drain(mark..));
+                ArenaNode::Object(start, arena.members.len())
+            },
+            Some('"') => {
+                let (start, end) = self.parse_arena_string(arena)?;
+                ArenaNode::Str(start, end)
+            },
+            Some('-') | Some('0'..='9') => {
+                // The number's text only passes through the buffer.
+                let start = arena.text.len();
+                self.consume_num_into(&mut arena.text);
+                let n = arena.text[start..].parse::<f64>();
+                arena.text.truncate(start);
+                match n {
+                    Ok(n) => ArenaNode::Num(n),
+                    Err(_) => return self.error(NumberParsing)
+                }
+            },
+            // Only the literals and errors are left.
+            _ => match self.parse_value()? {
+                Bool(b) => ArenaNode::Bool(b),
+                _ => ArenaNode::Null
+            }
+        };
+        arena.nodes.push(node);
+        Ok(arena.nodes.len() - 1)
+    }
+
+    // Reads a string literal straight into the arena's text
+    // buffer and returns its range there.
+    fn parse_arena_string(&mut self, arena: &mut Arena) -> Result<(usize, usize), JsonError> {
+        self.consume_whitespace();
+        if !self.ch_is('"') {
+            return self.error(UnclosedStringLiteral);
+        }
+        self.consume_char();
+        let start = arena.text.len();
+        self.read_string_contents('"', &mut arena.text)?;
+        Ok((start, arena.text.len()))
+    }
+
+    // Replaces the result with the error of invalid UTF-8 or a
+    // malformed comment, either of which cut the input short.
+    fn check_input<V>(&mut self, result: Result<V, JsonError>) -> Result<V, JsonError> {
+        if self.utf8_error.is_some_and(|invalid| invalid(&self.iter)) {
+            return self.error(InvalidUtf8);
+        }
+        match self.comment_error.take() {
+            Some(e) => Err(e),
+            None => result
+        }
+    }
+
+    /// Parses a complete document: a single value, optionally
+    /// surrounded by whitespace, followed by the end of input.
+    pub fn parse(&mut self) -> JsonResult {
+        let result = match self.parse_value() {
+            Ok(value) => {
+                self.consume_whitespace();
+                if self.eof() {
+                    Ok(value)
+                } else {
+                    self.error(TrailingCharacters)
+                }
+            },
+            e => e
+        };
+        self.check_input(result)
+    }
+
+    /// Like `parse`, but on failure also returns as much of the
+    /// tree as was parsed before the error, e.g. `[1, 2]` for
+    /// the truncated `[1, 2, {"a": `. Containers that were
+    /// still open are included with the members they had.
+    /// The partial tree is `None` if nothing could be parsed.
+    pub fn parse_keeping_partial(&mut self) -> Result<JsonValue, (Option<JsonValue>, JsonError)> {
+        self.keep_partial = true;
+        let result = match self.parse_value() {
+            Ok(value) => {
+                self.consume_whitespace();
+                if self.eof() {
+                    Ok(value)
+                } else {
+                    self.partial_error(value, self.error(TrailingCharacters))
+                }
+            },
+            e => e
+        };
+        self.keep_partial = false;
+        let result = self.check_input(result);
+        result.map_err(|e| (self.partial.take(), e))
+    }
+
+    /// The (line, col) of the current character.
+    pub fn position(&self) -> Position {
+        (self.line, self.col)
+    }
+
+    /// The current character, or None at the end of input.
+    pub fn current_char(&self) -> Option<char> {
+        self.ch
+    }
+
+    /// Errors that were skipped over in recovery mode.
+    pub fn errors(&self) -> &[JsonError] {
+        &self.errors
+    }
+
+    /// Parses a single value and stops right after it, leaving
+    /// any remaining input unconsumed.
+    pub fn parse_partial(&mut self) -> JsonResult {
+        let result = self.parse_value();
+        self.check_input(result)
+    }
+
+    /// Checks that the input is a well-formed document without
+    /// building the value tree.
+    pub fn validate(&mut self) -> Result<(), JsonError> {
+        self.discard = true;
+        let result = self.parse().map(|_| ());
+        self.discard = false;
+        result
+    }
+
+    /// Parses a value and also returns the (line, col) where it
+    /// starts and the (line, col) just past its last character.
+    pub fn parse_spanned(&mut self) -> SpannedResult {
+        self.consume_whitespace();
+        let start = (self.line, self.col);
+        let result = self.parse_value();
+        let value = self.check_input(result)?;
+        Ok((value, start, self.value_end))
+    }
+}
+
+// A node stored in an `Arena`. Strings are ranges into the
+// arena's text buffer, containers ranges into its item or
+// member lists.
+#[derive(Debug, Clone, Copy)]
+enum ArenaNode {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(usize, usize),
+    Array(usize, usize),
+    Object(usize, usize)
+}
+
+/// Storage for parsing documents without a heap allocation
+/// per node: all nodes, container children and string text
+/// live in a few shared buffers. The parsed tree borrows the
+/// arena and is read through `ArenaValue` handles. Call
+/// `clear` to reuse the buffers for the next document.
+#[derive(Debug, Default)]
+pub struct Arena {
+    nodes: Vec<ArenaNode>,
+    items: Vec<usize>,
+    members: Vec<((usize, usize), usize)>,
+    text: String,
+    item_stack: Vec<usize>,
+    member_stack: Vec<((usize, usize), usize)>
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    /// Parses a complete document into the arena. Numbers are
+    /// stored as f64. The input has to be strict JSON, as with
+    /// the default `ParserOptions`; none of the parser's
+    /// extensions are accepted.
+    pub fn parse(&mut self, input: &str) -> Result<ArenaValue<'_>, JsonError> {
+        let mut parser = JsonParser::new(input.chars());
+        let root = parser.parse_arena_value(self)?;
+        parser.consume_whitespace();
+        if !parser.eof() {
+            return parser.error(TrailingCharacters);
+        }
+        Ok(ArenaValue { arena: self, id: root })
+    }
+
+    /// Drops all parsed nodes while keeping the allocated buffers.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.items.clear();
+        self.members.clear();
+        self.text.clear();
+        self.item_stack.clear();
+        self.member_stack.clear();
+    }
+}
+
+/// Borrowed handle to a value parsed into an `Arena`.
+#[derive(Debug, Clone, Copy)]
+pub struct ArenaValue<'a> {
+    arena: &'a Arena,
+    id: usize
+}
+
+impl<'a> ArenaValue<'a> {
+    fn node(&self) -> ArenaNode {
+        self.arena.nodes[self.id]
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self.node(), ArenaNode::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.node() {
+            ArenaNode::Bool(b) => Some(b),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.node() {
+            ArenaNode::Num(n) => Some(n),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self.node() {
+            ArenaNode::Str(start, end) => Some(&self.arena.text[start..end]),
+            _ => None
+        }
+    }
+
+    /// Number of elements or members, 0 for scalars.
+    pub fn len(&self) -> usize {
+        match self.node() {
+            ArenaNode::Array(start, end) | ArenaNode::Object(start, end) => end - start,
+            _ => 0
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The array element at `index`.
+    pub fn at(&self, index: usize) -> Option<ArenaValue<'a>> {
+        match self.node() {
+            ArenaNode::Array(start, end) if start + index < end =>
+                Some(ArenaValue { arena: self.arena, id: self.arena.items[start + index] }),
+            _ => None
+        }
+    }
+
+    /// The object member with the given key.
+    pub fn get(&self, key: &str) -> Option<ArenaValue<'a>> {
+        match self.node() {
+            ArenaNode::Object(start, end) => self.arena.members[start..end].iter()
+                .find(|&&((ks, ke), _)| &self.arena.text[ks..ke] == key)
+                .map(|&(_, id)| ArenaValue { arena: self.arena, id }),
+            _ => None
+        }
+    }
+
+    /// Copies the value into an owned `JsonValue`.
+    pub fn to_value(&self) -> JsonValue {
+        let arena = self.arena;
+        match self.node() {
+            ArenaNode::Null => Null,
+            ArenaNode::Bool(b) => Bool(b),
+            ArenaNode::Num(n) => Num(n),
+            ArenaNode::Str(start, end) => Str(arena.text[start..end].to_string()),
+            ArenaNode::Array(start, end) => Array(arena.items[start..end].iter()
+                .map(|&id| ArenaValue { arena, id }.to_value())
+                .collect()),
+            ArenaNode::Object(start, end) => Object(arena.members[start..end].iter()
+                .map(|&((ks, ke), id)| (arena.text[ks..ke].into(), ArenaValue { arena, id }.to_value()))
+                .collect())
+        }
+    }
+}
+
+impl JsonParser<OwnedChars> {
+    /// Creates a parser that takes ownership of its input.
+    pub fn from_string(input: String) -> JsonParser<OwnedChars> {
+        JsonParser::new(OwnedChars::new(input))
+    }
+}
+
+/// Parses a value from an owned `String`.
+pub fn from_string(input: String) -> JsonResult {
+    JsonParser::from_string(input).parse()
+}
+
+/// Parses a value from a string slice.
+pub fn from_str(input: &str) -> JsonResult {
+    JsonParser::new(input.chars()).parse()
+}
+
+/// Parses with array error recovery and returns the best-effort
+/// value along with every error, e.g. for editor diagnostics.
+/// Broken array elements are skipped and reported; a fatal error
+/// ends the parse, keeping what was parsed so far. The value is
+/// `None` if nothing could be recovered.
+pub fn parse_lenient(input: &str) -> (Option<JsonValue>, Vec<JsonError>) {
+    let options = ParserOptions { recover_array_errors: true, ..ParserOptions::default() };
+    let mut parser = JsonParser::with_options(input.chars(), options);
+    let result = parser.parse_keeping_partial();
+    let mut errors = mem::take(&mut parser.errors);
+    match result {
+        Ok(value) => (Some(value), errors),
+        Err((partial, e)) => {
+            errors.push(e);
+            (partial, errors)
+        }
+    }
+}
+
+/// Like `from_str`, but fails with `LimitExceeded` without
+/// parsing if the input is longer than `max_bytes`.
+pub fn from_str_with_limit(input: &str, max_bytes: usize) -> JsonResult {
+    if input.len() > max_bytes {
+        return Err(JsonError::new(LimitExceeded));
+    }
+    from_str(input)
+}
+
+/// Parses a document from `reader`, decoding it as it is read
+/// instead of loading it into memory first. Read errors are
+/// reported as `Other`.
+pub fn from_reader<R: Read>(reader: R) -> JsonResult {
+    let mut parser = JsonParser::from_reader(reader);
+    let result = parser.parse();
+    if parser.iter.error.is_some() {
+        return Err(JsonError::new(Other));
+    }
+    result
+}
+
+/// Like `from_reader`, but stops reading and fails with
+/// `LimitExceeded` as soon as more than `max_bytes` bytes
+/// have been read.
+pub fn from_reader_with_limit<R: Read>(reader: R, max_bytes: usize) -> JsonResult {
+    // Read one byte past the limit to tell whether there's more.
+    let mut parser = JsonParser::from_reader(reader.take((max_bytes as u64).saturating_add(1)));
+    let result = parser.parse();
+    if parser.iter.bytes_read > max_bytes {
+        return Err(JsonError::new(LimitExceeded));
+    }
+    if parser.iter.error.is_some() {
+        return Err(JsonError::new(Other));
+    }
+    result
+}
+
+/// Parses a document whose top-level value has to be an object.
+pub fn parse_object_value(input: &str) -> JsonResult {
+    match JsonParser::new(input.chars()).parse()? {
+        v @ Object(_) => Ok(v),
+        _ => Err(JsonError::new(TypeMismatch))
+    }
+}
+
+/// Parses a document whose top-level value has to be an array.
+pub fn parse_array_value(input: &str) -> JsonResult {
+    match JsonParser::new(input.chars()).parse()? {
+        v @ Array(_) => Ok(v),
+        _ => Err(JsonError::new(TypeMismatch))
+    }
+}
+
+/// Parses back-to-back top-level values such as `{...}{...}`,
+/// optionally separated by whitespace. Stops after the first error.
+pub fn parse_stream(input: &str) -> Vec<JsonResult> {
+    let mut parser = JsonParser::new(input.chars());
+    let mut results = Vec::new();
+    loop {
+        parser.consume_whitespace();
+        if parser.eof() {
+            break;
+        }
+        let result = parser.parse_partial();
+        let failed = result.is_err();
+        results.push(result);
+        if failed {
+            break;
+        }
+    }
+    results
+}
+
+// Iterator behind `array_stream`.
+struct ArrayStream<'a> {
+    parser: JsonParser<str::Chars<'a>>,
+    started: bool,
+    done: bool
+}
+
+impl<'a> ArrayStream<'a> {
+    // Moves past the `[` or `,` in front of the next element.
+    // Returns None once the closing `]` has been reached.
+    fn advance(&mut self) -> Option<JsonResult> {
+        let parser = &mut self.parser;
+        parser.consume_whitespace();
+        if !self.started {
+            self.started = true;
+            if !parser.ch_is('[') {
+                return Some(parser.error(UnexpectedCharacter));
+            }
+            parser.consume_char();
+            parser.consume_whitespace();
+            if !parser.ch_is(']') {
+                return Some(parser.parse_value());
+            }
+        } else if parser.ch_is(',') {
+            parser.consume_char();
+            return Some(parser.parse_value());
+        } else if !parser.ch_is(']') {
+            let reason = if parser.eof() { UnclosedArray } else { UnexpectedCharacter };
+            return Some(parser.error(reason));
+        }
+        parser.consume_char();
+        parser.consume_whitespace();
+        if parser.eof() {
+            None
+        } else {
+            Some(parser.error(TrailingCharacters))
+        }
+    }
+}
+
+impl<'a> Iterator for ArrayStream<'a> {
+    type Item = JsonResult;
+
+    fn next(&mut self) -> Option<JsonResult> {
+        if self.done {
+            return None;
+        }
+        let result = self.advance();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
+
+/// Parser for input that arrives in pieces, e.g. from a
+/// network connection. Chunks may split a token anywhere.
+/// The chunks are buffered, and `feed` checks everything
+/// buffered so far with the regular parser, so the first
+/// syntax error is reported as soon as the chunk containing
+/// it arrives. Each check starts over at the beginning of the
+/// buffer, so prefer fewer, larger chunks for big documents.
+/// `finish` parses the buffered document.
+#[derive(Debug, Default)]
+pub struct PushParser {
+    buffer: String,
+    complete: bool,
+    error: Option<JsonError>
+}
+
+impl PushParser {
+    pub fn new() -> PushParser {
+        PushParser::default()
+    }
+
+    /// Appends the next chunk of input. Fails with the first
+    /// syntax error found so far, including non-whitespace
+    /// input after a complete document (`TrailingCharacters`).
+    /// An error in a token cut off at the end of the input so
+    /// far is only reported once the token ends.
+    pub fn feed(&mut self, chunk: &str) -> Result<(), JsonError> {
+        if let Some(ref e) = self.error {
+            return Err(e.clone());
+        }
+        self.buffer.push_str(chunk);
+        // An error the parser ran into after reaching the end of
+        // the buffer may go away once more input arrives.
+        let ran_out = Cell::new(false);
+        let mut chars = self.buffer.chars();
+        let mut parser = JsonParser::new(iter::from_fn(|| {
+            let c = chars.next();
+            if c.is_none() {
+                ran_out.set(true);
+            }
+            c
+        }));
+        let result = parser.validate();
+        // A trailing scalar could still grow, e.g. `4` into `42`.
+        self.complete = result.is_ok() &&
+            !self.buffer.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '.');
+        match result {
+            Err(e) if !ran_out.get() => {
+                self.error = Some(e.clone());
+                Err(e)
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// Whether a complete top-level value has arrived. A
+    /// top-level scalar only counts as complete once some
+    /// whitespace follows it, since more digits or characters
+    /// could still arrive.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Parses everything fed so far as one document.
+    pub fn finish(self) -> JsonResult {
+        JsonParser::from_string(self.buffer).parse()
+    }
+}
+
+/// Lazily parses the elements of a top-level array, yielding
+/// one at a time instead of building the whole `Vec`.
+/// Iteration ends after the closing `]` or the first error.
+pub fn array_stream(input: &str) -> impl Iterator<Item = JsonResult> + '_ {
+    ArrayStream {
+        parser: JsonParser::new(input.chars()),
+        started: false,
+        done: false
+    }
+}
+
+/// Escapes a string the way the serializer does, without
+/// adding the surrounding quotes.
+pub fn escape_json_string(s: &str) -> String {
+    escape_str(s).into_owned()
+}
+
+/// Resolves the escape sequences in the contents of a JSON
+/// string literal (without its quotes), e.g. `a\"b\u00e9`.
+/// Fails with `InvalidEscape` on a malformed sequence.
+pub fn unescape_json_string(s: &str) -> Result<String, JsonError> {
+    let mut parser = JsonParser::new(s.chars());
+    let mut result = String::with_capacity(s.len());
+    // The parser skips a leading byte order mark, keep it here.
+    if s.starts_with('\u{feff}') {
+        result.push('\u{feff}');
+    }
+    while let Some(c) = parser.ch {
+        if c == '\\' {
+            parser.consume_char();
+            match parser.parse_escape() {
+                Some(c) => result.push(c),
+                None => return parser.error(InvalidEscape)
+            }
+        } else {
+            result.push(c);
+            parser.consume_char();
+        }
+    }
+    Ok(result)
+}
+
+/// Checks the structure of a document without building
+/// the value, returning the first error.
+pub fn validate_structure(input: &str) -> Result<(), JsonError> {
+    JsonParser::new(input.chars()).validate()
+}
+
+/// Returns whether the input is a well-formed document.
+pub fn is_valid(input: &str) -> bool {
+    validate_structure(input).is_ok()
+}
+
+impl<'a> JsonParser<ByteChars<'a>> {
+    /// Creates a parser reading UTF-8 encoded bytes, decoding
+    /// them to chars as it goes. Invalid UTF-8 fails the parse
+    /// with `InvalidUtf8` at the position of the bad sequence.
+    pub fn from_bytes(input: &'a [u8]) -> JsonParser<ByteChars<'a>> {
+        let mut parser = JsonParser::new(ByteChars::new(input));
+        parser.utf8_error = Some(|iter| iter.invalid);
+        parser
+    }
+}
+
+impl<R: Read> JsonParser<ReaderChars<R>> {
+    /// Creates a parser decoding UTF-8 from a reader as it goes.
+    /// Invalid UTF-8 fails the parse with `InvalidUtf8` at the
+    /// position of the bad sequence.
+    pub fn from_reader(reader: R) -> JsonParser<ReaderChars<R>> {
+        let mut parser = JsonParser::new(ReaderChars::new(reader));
+        parser.utf8_error = Some(|iter| iter.invalid);
+        parser
+    }
+}
+
+const USAGE: &str = "usage: json-rs [--pretty [--indent N] | --compact | --validate] [<file> | -]";
+
+// Command line options of the binary.
+#[derive(Debug, PartialEq)]
+struct CliOptions {
+    // File to read, None to read stdin.
+    path: Option<String>,
+    // Indentation width when pretty-printing, None for compact output.
+    indent: Option<usize>,
+    // Only check the input, printing nothing on success.
+    validate: bool
+}
+
+// Parses the command line arguments, without the program name.
+fn parse_args(args: &[String]) -> Result<CliOptions, String> {
+    let mut path = None;
+    let mut pretty = false;
+    let mut indent = 2;
+    let mut validate = false;
+    let mut seen_path = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--pretty" => pretty = true,
+            "--compact" => pretty = false,
+            "--validate" => validate = true,
+            "--indent" => {
+                indent = iter.next().and_then(|n| n.parse().ok())
+                    .ok_or_else(|| format!("--indent expects a number\n{}", USAGE))?;
+                pretty = true;
+            },
+            flag if flag.starts_with('-') && flag != "-" => return Err(format!("unknown flag {}\n{}", flag, USAGE)),
+            _ if seen_path => return Err(USAGE.to_string()),
+            "-" => seen_path = true,
+            file => {
+                path = Some(file.to_string());
+                seen_path = true;
+            }
+        }
+    }
+    Ok(CliOptions {
+        path,
+        indent: if pretty { Some(indent) } else { None },
+        validate
+    })
+}
+
+// Runs the binary with the given arguments, reading `input`
+// if no file is given and writing the output to `out`.
+// Errors are returned as the message to print.
+fn run<R: Read, W: Write>(args: &[String], input: R, out: &mut W) -> Result<(), String> {
+    let options = parse_args(args)?;
+    match options.path {
+        Some(ref path) => {
+            let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+            process_input(file, &options, out)
+        },
+        None => process_input(input, &options, out)
+    }
+}
+
+// Parses the input and prints it as the options ask for.
+fn process_input<R: Read, W: Write>(input: R, options: &CliOptions, out: &mut W) -> Result<(), String> {
+    if options.validate {
+        return JsonParser::from_reader(input).validate().map_err(|e| e.to_string());
+    }
+    let value = from_reader(input).map_err(|e| e.to_string())?;
+    let output = match options.indent {
+        Some(indent) => to_pretty_string(&value, indent),
+        None => value.to_string()
+    };
+    writeln!(out, "{}", output).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::mem;
+    //use test::*;
+
+    #[test]
+    fn parse_null() {
+        let mut parser = JsonParser::new("   null  ".chars());
+        let result = parser.parse_null();
+        assert_eq!(result, Ok(Null));
+    }
+
+    #[test]
+    fn parse_number() {
+        let mut parser = JsonParser::new("  4.2342 ".chars());
+
+        let result = parser.parse_num();
+        assert_eq!(result, Ok(Num(4.2342)));
+    }
+
+    #[test]
+    fn parse_number_2() {
+        let mut parser = JsonParser::new("  16237  ".chars());
+        let result = parser.parse_num();
+        assert_eq!(result, Ok(Num(16237.0)));
+    }
+
+    #[test]
+    fn parse_number_error() {
+        let mut parser = JsonParser::new("  abcdef  ".chars());
+        let result = parser.parse_num();
+        match result {
+            Ok(_) => panic!(),
+            Err(e) => assert_eq!(e.reason, NumberParsing)
+        }
+    }
+
+    #[test]
+    fn parse_string() {
+        let mut parser = JsonParser::new("  \"String\" ".chars());
+        let result = parser.parse_string();
+        assert_eq!(result, Ok(Str("String".to_string())));
+    }
+
+    #[test]
+    fn parse_string_error() {
+        let mut parser = JsonParser::new("\"String".chars());
+        let result = parser.parse_string();
+        match result {
+            Ok(_) => panic!(),
+            Err(err) => assert_eq!(err.reason, UnclosedStringLiteral)
+        }
+        
+    }
+
+    #[test]
+    fn parse_string_control_char() {
+        let options = ParserOptions { reject_control_characters: true, ..ParserOptions::default() };
+        let mut parser = JsonParser::with_options("\"a\tb\"".chars(), options);
+        let result = parser.parse_string();
+        match result {
+            Ok(_) => panic!(),
+            Err(err) => assert_eq!(err.reason, InvalidControlCharacter)
+        }
+
+        parser = JsonParser::new("\"a\tb\"".chars());
+        let result = parser.parse_string();
+        assert_eq!(result, Ok(Str("a\tb".to_string())));
+    }
+
+    #[test]
+    fn parse_bool() {
+        let mut parser = JsonParser::new("false".chars());
+        let result = parser.parse_bool();
+        assert_eq!(result, Ok(Bool(false)));
+
+        parser = JsonParser::new("true".chars());
+        let result = parser.parse_bool();
+        assert_eq!(result, Ok(Bool(true)));
+    }
+
+    #[test]
+    fn parse_bool_array() {
+        let mut parser = JsonParser::new("[ true , true , true ]".chars());
+        let result = parser.parse_array();
+        match result {
+            Ok(val) => {
+                let expected = Array(vec![Bool(true), Bool(true), Bool(true)]);
+                assert_eq!(val, expected);
+            }
+            Err(why) => {
+                panic!("{:?}", why);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_num_array() {
+        let mut parser = JsonParser::new("[1.2, 4.2, 1.2, 4.5]".chars());
+        let result = parser.parse_array();
+        match result {
+            Ok(value) => {
+                let expected = Array(vec![Num(1.2), Num(4.2), Num(1.2), Num(4.5)]);
+                assert_eq!(expected, value);
+            }
+            Err(err) => {
+                panic!("{:?}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_nested_array() {
+        let mut parser = JsonParser::new("[[true, true], [true, false]]".chars());
+        let result = parser.parse_value();
+        match result {
+            Ok(value) => {
+                let expected = Array(vec![
+                    Array(vec![Bool(true), Bool(true)]),
+                    Array(vec![Bool(true), Bool(false)])]);
+                assert_eq!(expected, value);
+            }
+            Err(err) => {
+                panic!("{:?}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_object_simple() {
+        let mut parser = JsonParser::new("{\"label\" : 1.5}".chars());
+        let result = parser.parse_object();
+
+        let mut obj = Map::new();
+        obj.insert("label".into(), Num(1.5));
+
+        assert_eq!(Object(obj), result.unwrap());
+    }
+
+    #[test]
+    fn parse_object_array() {
+        let mut parser = JsonParser::new("{\"label\" : [true, true, true]}".chars());
+        let result = parser.parse_object();
+
+        let mut obj = Map::new();
+        obj.insert("label".into(), Array(vec![Bool(true), Bool(true), Bool(true)]));
+
+        assert_eq!(Object(obj), result.unwrap());
+
+    }
+    
+    #[test]
+    fn index_array() {
+    	let mut parser = JsonParser::new("[1, 2, 3, 4, 5]".chars());
+    	let result = parser.parse().unwrap();
+    	for i in 1..6 {
+    		assert_eq!(result[i-1], Num(i as f64));
+    	}
+    }
+    
+    #[test]
+    fn index_object() {
+    	let mut parser = JsonParser::new("{\"label\" : 1.5}".chars());
+        let result = parser.parse_object().unwrap();
+        let indexed = result["label"].clone();
+        let expected = Num(1.5);
+        assert_eq!(indexed, expected);
+    }
+    
+    #[test]
+    fn build_nested_object() {
+        let built = ObjectBuilder::new()
+            .key("name", Str("json-rs".to_string()))
+            .key("tags", ArrayBuilder::new()
+                 .push(Num(1.0))
+                 .push(Bool(true))
+                 .build())
+            .key("owner", ObjectBuilder::new()
+                 .key("id", Null)
+                 .build())
+            .build();
+
+        let mut parser = JsonParser::new(
+            "{\"name\": \"json-rs\", \"tags\": [1, true], \"owner\": {\"id\": null}}".chars());
+        assert_eq!(built, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn from_json_scalars() {
+        let mut parser = JsonParser::new("{\"id\": 42, \"name\": \"x\", \"tags\": [\"a\", \"b\"]}".chars());
+        let value = parser.parse().unwrap();
+
+        assert_eq!(i64::from_json(&value["id"]), Ok(42));
+        assert_eq!(f64::from_json(&value["id"]), Ok(42.0));
+        assert_eq!(String::from_json(&value["name"]), Ok("x".to_string()));
+        assert_eq!(Vec::<String>::from_json(&value["tags"]),
+                   Ok(vec!["a".to_string(), "b".to_string()]));
+        assert_eq!(Option::<f64>::from_json(&Null), Ok(None));
+
+        let mut parser = JsonParser::new("{\"a\": 1, \"b\": 2}".chars());
+        let map = HashMap::<String, u32>::from_json(&parser.parse().unwrap()).unwrap();
+        assert_eq!(map["a"], 1);
+        assert_eq!(map["b"], 2);
+    }
+
+    #[test]
+    fn from_json_type_mismatch() {
+        let result = String::from_json(&Num(1.0));
+        assert_eq!(result.unwrap_err().reason, TypeMismatch);
+
+        let result = i64::from_json(&Num(1.5));
+        assert_eq!(result.unwrap_err().reason, TypeMismatch);
+
+        let result = Vec::<bool>::from_json(&Array(vec![Bool(true), Null]));
+        assert_eq!(result.unwrap_err().reason, TypeMismatch);
+    }
+
+    #[test]
+    fn flatten_nested() {
+        let mut parser = JsonParser::new(
+            "{\"users\": [{\"name\": \"a\", \"admin\": true}, {\"name\": \"b\"}], \"a/b\": null}".chars());
+        let value = parser.parse().unwrap();
+        let expected = vec![
+            ("/a~1b".to_string(), Null),
+            ("/users/0/admin".to_string(), Bool(true)),
+            ("/users/0/name".to_string(), Str("a".to_string())),
+            ("/users/1/name".to_string(), Str("b".to_string()))];
+        assert_eq!(value.flatten(), expected);
+    }
+
+    #[test]
+    fn unflatten_round_trip() {
+        let mut parser = JsonParser::new(
+            "{\"users\": [{\"name\": \"a\", \"ids\": [1, 2]}, {\"name\": \"b\"}], \"x~y\": null}".chars());
+        let value = parser.parse().unwrap();
+        let pairs = value.flatten();
+        assert_eq!(JsonValue::unflatten(pairs), Ok(value));
+    }
+
+    #[test]
+    fn unflatten_conflict() {
+        let pairs = vec![("/a".to_string(), Num(1.0)), ("/a/b".to_string(), Num(2.0))];
+        assert_eq!(JsonValue::unflatten(pairs).unwrap_err().reason, PathConflict);
+
+        let pairs = vec![("a".to_string(), Num(1.0))];
+        assert_eq!(JsonValue::unflatten(pairs).unwrap_err().reason, InvalidPointer);
+    }
+
+    #[test]
+    fn type_names() {
+        assert_eq!(Null.type_name(), "null");
+        assert_eq!(Bool(true).type_name(), "bool");
+        assert_eq!(Num(1.0).type_name(), "number");
+        assert_eq!(Str(String::new()).type_name(), "string");
+        assert_eq!(Array(Vec::new()).type_name(), "array");
+        assert_eq!(Object(Map::new()).type_name(), "object");
+    }
+
+    #[test]
+    fn get_or_null_chain() {
+        let mut parser = JsonParser::new("{\"a\": {\"b\": 1}}".chars());
+        let value = parser.parse().unwrap();
+        assert_eq!(*value.get_or_null("a").get_or_null("b"), Num(1.0));
+        assert_eq!(*value.get_or_null("x").get_or_null("y"), Null);
+        assert_eq!(*value.get_or_null("a").get_or_null("b").get_or_null("c"), Null);
+    }
+
+    #[test]
+    fn parse_leading_bom() {
+        let mut parser = JsonParser::new("\u{feff}true".chars());
+        assert_eq!(parser.parse(), Ok(Bool(true)));
+
+        let mut parser = JsonParser::new(" \u{feff}true".chars());
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn parse_unclosed_array_eof() {
+        let mut parser = JsonParser::new("[1,2".chars());
+        let err = parser.parse().unwrap_err();
+        assert_eq!(err, JsonError { reason: UnclosedArray, line: 1, col: 1 });
+    }
+
+    #[test]
+    fn parse_unclosed_object_eof() {
+        let mut parser = JsonParser::new("{\"a\":1".chars());
+        let err = parser.parse().unwrap_err();
+        assert_eq!(err, JsonError { reason: UnclosedObject, line: 1, col: 1 });
+
+        let mut parser = JsonParser::new(" {\"a\":".chars());
+        let err = parser.parse().unwrap_err();
+        assert_eq!(err, JsonError { reason: UnclosedObject, line: 1, col: 2 });
+    }
+
+    #[test]
+    fn parse_u64() {
+        let mut parser = JsonParser::new("18446744073709551615".chars());
+        let value = parser.parse().unwrap();
+        assert_eq!(value, U64(u64::MAX));
+        assert_eq!(value.as_u64(), Some(u64::MAX));
+        assert_eq!(value.to_string(), "18446744073709551615");
+
+        let mut parser = JsonParser::new("9223372036854775808".chars());
+        assert_eq!(parser.parse(), Ok(U64(i64::MAX as u64 + 1)));
+
+        let mut parser = JsonParser::new("18446744073709551616".chars());
+        assert_eq!(parser.parse(), Ok(Num(18446744073709551616.0)));
+
+        assert_eq!(Num(42.0).as_u64(), Some(42));
+        assert_eq!(Num(-1.0).as_u64(), None);
+    }
+
+    #[test]
+    fn set_creates_containers() {
+        let mut value = Object(Map::new());
+        value.set("/a/b/0", Bool(true)).unwrap();
+        let mut parser = JsonParser::new("{\"a\": {\"b\": [true]}}".chars());
+        assert_eq!(value, parser.parse().unwrap());
+
+        value.set("/a/c", Num(1.0)).unwrap();
+        value.set("/a/b/1", Null).unwrap();
+        value.set("/a/b/0", Bool(false)).unwrap();
+        value.set("/x/-/y", Num(2.0)).unwrap();
+        let mut parser = JsonParser::new(
+            "{\"a\": {\"b\": [false, null], \"c\": 1}, \"x\": [{\"y\": 2}]}".chars());
+        assert_eq!(value, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn set_conflict() {
+        let mut value = Object(Map::new());
+        value.set("/a", Num(1.0)).unwrap();
+        assert_eq!(value.set("/a/b", Null).unwrap_err().reason, PathConflict);
+
+        value.set("/list/0", Null).unwrap();
+        assert_eq!(value.set("/list/5", Null).unwrap_err().reason, InvalidPointer);
+    }
+
+    #[test]
+    fn set_append_token() {
+        let mut value = JsonParser::new("{\"items\": [1, 2], \"obj\": {\"k\": 1}}".chars()).parse().unwrap();
+        value.set("/items/-", Num(3.0)).unwrap();
+        value.set("/items/-", Array(vec![])).unwrap();
+        value.set("/items/3/-", Bool(true)).unwrap();
+        assert_eq!(value.pointer("/items"),
+                   Some(&Array(vec![Num(1.0), Num(2.0), Num(3.0), Array(vec![Bool(true)])])));
+
+        assert_eq!(value.set("/obj/-", Null).unwrap_err().reason, InvalidPointer);
+        assert_eq!(value.set("/items/0/-", Null).unwrap_err().reason, PathConflict);
+    }
+
+    #[test]
+    fn parse_literals_ignore_case() {
+        let options = ParserOptions { case_insensitive_literals: true, ..ParserOptions::default() };
+        let mut parser = JsonParser::with_options("[TRUE, False, Null]".chars(), options);
+        assert_eq!(parser.parse(), Ok(Array(vec![Bool(true), Bool(false), Null])));
+
+        let mut parser = JsonParser::new("TRUE".chars());
+        assert_eq!(parser.parse().unwrap_err().reason, ExpectedBool);
+        let mut parser = JsonParser::new("Null".chars());
+        assert_eq!(parser.parse().unwrap_err().reason, ExpectedNull);
+    }
+
+    #[test]
+    fn escape_str_borrows() {
+        assert!(matches!(escape_str("plain ascii"), Cow::Borrowed("plain ascii")));
+        assert!(matches!(escape_str("gr\u{fc}\u{df}e"), Cow::Borrowed(_)));
+
+        let escaped = escape_str("a\"b\\c\nd\u{1}");
+        assert!(matches!(escaped, Cow::Owned(_)));
+        assert_eq!(escaped, "a\\\"b\\\\c\\nd\\u0001");
+    }
+
+    #[test]
+    fn print_escaped_string() {
+        let value = Str("say \"hi\"\t".to_string());
+        assert_eq!(value.to_string(), "\"say \\\"hi\\\"\\t\"");
+    }
+
+    #[test]
+    fn parse_bytes_matches_chars() {
+        let data = include_str!("../MOCK_DATA.json");
+        let from_chars = JsonParser::new(data.chars()).parse();
+        let from_bytes = JsonParser::from_bytes(data.as_bytes()).parse();
+        assert!(from_chars.is_ok());
+        assert_eq!(from_chars, from_bytes);
+
+        let mut parser = JsonParser::from_bytes("[\"gr\u{fc}\u{df}e\", 1]".as_bytes());
+        assert_eq!(parser.parse(), Ok(Array(vec![Str("gr\u{fc}\u{df}e".to_string()), Num(1.0)])));
+    }
+
+    #[test]
+    fn parse_bytes_invalid_utf8() {
+        let parse = |input: &[u8]| JsonParser::from_bytes(input).parse().map_err(|e| (e.reason, e.line, e.col));
+        assert_eq!(parse(b"\"a\xffb\""), Err((InvalidUtf8, 1, 3)));
+        assert_eq!(parse(b"[1, \"\xc3\"]"), Err((InvalidUtf8, 1, 6)));
+        assert_eq!(parse(b"[1,\n \xe2\x82]"), Err((InvalidUtf8, 2, 2)));
+        assert_eq!(parse(b"true \xff"), Err((InvalidUtf8, 1, 6)));
+        assert_eq!(parse(b"\"\xed\xa0\x80\""), Err((InvalidUtf8, 1, 2)));
+        assert_eq!(parse(b"\"\xe2\x82\xac\""), Ok(Str("\u{20ac}".to_string())));
+
+        let from_bytes = JsonParser::from_bytes(b"[1,\n  x]").parse();
+        let from_chars = JsonParser::new("[1,\n  x]".chars()).parse();
+        assert!(from_bytes.is_err());
+        assert_eq!(from_bytes, from_chars);
+    }
+
+    #[test]
+    fn parse_number_underscores() {
+        let options = ParserOptions { allow_number_underscores: true, ..ParserOptions::default() };
+        let mut parser = JsonParser::with_options("1_000".chars(), options.clone());
+        assert_eq!(parser.parse_num(), Ok(Num(1000.0)));
+
+        let mut parser = JsonParser::with_options("1_000.5_5".chars(), options.clone());
+        assert_eq!(parser.parse_num(), Ok(Num(1000.55)));
+
+        for input in &["_1", "1_", "1__0", "1_.5"] {
+            let mut parser = JsonParser::with_options(input.chars(), options.clone());
+            assert_eq!(parser.parse_num().unwrap_err().reason, NumberParsing);
+        }
+
+        let mut parser = JsonParser::new("[1_000]".chars());
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn to_bytes_matches_to_string() {
+        let mut parser = JsonParser::new(
+            "{\"a\": [1, 2.5, \"x\ty\"], \"b\": {\"c\": null}}".chars());
+        let value = parser.parse().unwrap();
+        assert_eq!(to_bytes(&value), value.to_string().into_bytes());
+        assert_eq!(to_bytes(&Array(Vec::new())), b"[]");
+        assert_eq!(to_bytes(&Object(Map::new())), b"{}");
+    }
+
+    #[test]
+    fn hash_set_dedup() {
+        use std::collections::HashSet;
+
+        let mut set = HashSet::new();
+        for _ in 0..3 {
+            let mut parser = JsonParser::new(
+                "{\"a\": 1, \"b\": [true, null], \"c\": {\"d\": \"x\"}}".chars());
+            set.insert(parser.parse().unwrap());
+        }
+        set.insert(Num(0.0));
+        set.insert(Num(-0.0));
+        set.insert(Num(1.0));
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&Num(-0.0)));
+    }
+
+    #[test]
+    fn entry_counter() {
+        let mut value = Object(Map::new());
+        value.entry("count").or_insert(Num(0.0));
+        for _ in 0..3 {
+            value.entry("count").and_modify(|v| {
+                if let Num(ref mut n) = *v {
+                    *n += 1.0;
+                }
+            });
+        }
+        assert_eq!(value["count"], Num(3.0));
+    }
+
+    fn runtime_parser(count: usize) -> JsonParser<OwnedChars> {
+        let items: Vec<String> = (0..count).map(|i| i.to_string()).collect();
+        JsonParser::from_string(format!("[{}]", items.join(", ")))
+    }
+
+    fn runtime_value(name: &str) -> JsonValue {
+        from_string(format!("{{\"name\": \"{}\"}}", name)).unwrap()
+    }
+
+    #[test]
+    fn parse_owned_string() {
+        let mut parser = runtime_parser(3);
+        assert_eq!(parser.parse(), Ok(Array(vec![Num(0.0), Num(1.0), Num(2.0)])));
+
+        let value = runtime_value("gr\u{fc}n");
+        assert_eq!(value["name"], Str("gr\u{fc}n".to_string()));
+    }
+
+    #[test]
+    fn pretty_print() {
+        let value = ObjectBuilder::new()
+            .key("a", ArrayBuilder::new().push(Num(1.0)).push(Array(Vec::new())).build())
+            .build();
+        assert_eq!(to_pretty_string(&value, 2), "{\n  \"a\": [\n    1,\n    []\n  ]\n}");
+        assert_eq!(to_pretty_string(&Num(1.0), 2), "1");
+    }
+
+    #[test]
+    fn pretty_writer_matches_string() {
+        let data = include_str!("../test.json");
+        let value = JsonParser::new(data.chars()).parse().unwrap();
+        let mut buf: Vec<u8> = Vec::new();
+        to_pretty_writer(&value, &mut buf, 4).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), to_pretty_string(&value, 4));
+    }
+
+    #[test]
+    fn parse_spanned_value() {
+        let mut parser = JsonParser::new("  true  ".chars());
+        assert_eq!(parser.parse_spanned(), Ok((Bool(true), (1, 3), (1, 7))));
+
+        let mut parser = JsonParser::new(" [1, 2] ".chars());
+        let (_, start, end) = parser.parse_spanned().unwrap();
+        assert_eq!((start, end), ((1, 2), (1, 8)));
+
+        let options = ParserOptions { allow_comments: true, ..ParserOptions::default() };
+        let mut parser = JsonParser::with_options("true /* c */ ".chars(), options.clone());
+        assert_eq!(parser.parse_spanned(), Ok((Bool(true), (1, 1), (1, 5))));
+        let mut parser = JsonParser::with_options("// head\n[1, /* x */ 2] // tail".chars(), options);
+        let (_, start, end) = parser.parse_spanned().unwrap();
+        assert_eq!((start, end), ((2, 1), (2, 15)));
+    }
+
+    #[test]
+    fn parse_trailing_whitespace() {
+        let mut parser = JsonParser::new("true\n".chars());
+        assert_eq!(parser.parse(), Ok(Bool(true)));
+
+        let mut parser = JsonParser::new("{\"a\": 1}\r\n\t \n".chars());
+        assert!(parser.parse().is_ok());
+    }
+
+    #[test]
+    fn parse_trailing_characters() {
+        let mut parser = JsonParser::new("true\n x".chars());
+        let err = parser.parse().unwrap_err();
+        assert_eq!(err.reason, TrailingCharacters);
+    }
+
+    #[test]
+    fn find_all_numbers() {
+        let mut parser = JsonParser::new(
+            "{\"a\": 5, \"b\": [11, {\"c\": 42, \"d\": \"12\"}], \"e\": {\"f\": [3, 100]}}".chars());
+        let value = parser.parse().unwrap();
+        let mut found: Vec<f64> = value
+            .find_all(|v| matches!(*v, Num(n) if n > 10.0))
+            .into_iter()
+            .map(|v| v.clone().get_num().unwrap())
+            .collect();
+        found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(found, vec![11.0, 42.0, 100.0]);
+
+        assert_eq!(value.find_all(|v| v.type_name() == "array").len(), 2);
+    }
+
+    #[test]
+    fn default_is_null() {
+        assert_eq!(JsonValue::default(), Null);
+
+        let mut value = Num(1.0);
+        assert_eq!(mem::take(&mut value), Num(1.0));
+        assert_eq!(value, Null);
+    }
+
+    #[test]
+    fn parse_top_level_container() {
+        assert!(parse_object_value("{\"a\": 1}").is_ok());
+        assert!(parse_array_value("[1, 2]").is_ok());
+
+        assert_eq!(parse_object_value("[1, 2]").unwrap_err().reason, TypeMismatch);
+        assert_eq!(parse_object_value("42").unwrap_err().reason, TypeMismatch);
+        assert_eq!(parse_array_value("\"x\"").unwrap_err().reason, TypeMismatch);
+        assert_eq!(parse_array_value("[1,").unwrap_err().reason, UnclosedArray);
+    }
+
+    #[test]
+    fn pointer_exists() {
+        let mut parser = JsonParser::new("{\"a\": {\"b\": [1, {\"c\": null}]}, \"x/y\": 2}".chars());
+        let value = parser.parse().unwrap();
+        assert!(value.has_pointer(""));
+        assert!(value.has_pointer("/a/b"));
+        assert!(value.has_pointer("/a/b/1/c"));
+        assert!(value.has_pointer("/x~1y"));
+        assert_eq!(value.pointer("/a/b/0"), Some(&Num(1.0)));
+
+        assert!(!value.has_pointer("/a/c"));
+        assert!(!value.has_pointer("/a/b/2"));
+        assert!(!value.has_pointer("/a/b/01"));
+        assert!(!value.has_pointer("/a/b/0/c"));
+        assert!(!value.has_pointer("a"));
+    }
+
+    #[test]
+    fn approx_eq_numbers() {
+        let sum = Array(vec![Num(0.1 + 0.2), Str("x".to_string())]);
+        let expected = Array(vec![Num(0.3), Str("x".to_string())]);
+        assert!(sum != expected);
+        assert!(sum.approx_eq(&expected, 1e-9));
+        assert!(!sum.approx_eq(&Array(vec![Num(0.31), Str("x".to_string())]), 1e-9));
+
+        let a = ObjectBuilder::new().key("n", Num(1.0 / 3.0)).build();
+        let b = ObjectBuilder::new().key("n", Num(0.333333)).build();
+        assert!(a.approx_eq(&b, 1e-6));
+        assert!(!a.approx_eq(&ObjectBuilder::new().build(), 1e-6));
+    }
+
+    #[test]
+    fn sorted_arrays() {
+        let a = parse_array_value("[3, \"b\", null, [2, 1], true, {\"k\": [1, 0]}, 1]").unwrap();
+        let b = parse_array_value("[{\"k\": [0, 1]}, [1, 2], 1, \"b\", true, 3, null]").unwrap();
+        assert!(a != b);
+
+        let sorted = a.sorted();
+        assert_eq!(sorted, b.sorted());
+        assert_eq!(sorted.to_string(), "[null,true,1,3,\"b\",[1,2],{\"k\":[0,1]}]");
+    }
+
+    #[test]
+    fn validate_only() {
+        assert!(is_valid("{\"a\": [1, true, null, {\"b\": \"c\"}]}"));
+        assert!(!is_valid("{\"a\": [1, true"));
+        assert!(!is_valid("[1, 2] 3"));
+        assert_eq!(validate_structure("{\"a\" 1}").unwrap_err().reason, ExpectedColon);
+
+        let mut big = "[".to_string();
+        for _ in 0..100000 {
+            big.push_str("{\"a\": [1, 2, 3], \"b\": \"text\"},");
+        }
+        big.push_str("null]");
+        assert!(is_valid(&big));
+    }
+
+    #[test]
+    fn parse_concatenated() {
+        let results = parse_stream("{\"a\":1}{\"b\":2}");
+        assert_eq!(results, vec![
+            Ok(ObjectBuilder::new().key("a", Num(1.0)).build()),
+            Ok(ObjectBuilder::new().key("b", Num(2.0)).build())]);
+
+        let results = parse_stream(" 1 [2]\n\"x\" {");
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[2], Ok(Str("x".to_string())));
+        assert_eq!(results[3].as_ref().unwrap_err().reason, UnclosedObject);
+
+        assert!(parse_stream("  ").is_empty());
+    }
+
+    #[test]
+    fn parse_array_recovery() {
+        let options = ParserOptions { recover_array_errors: true, ..ParserOptions::default() };
+        let mut parser = JsonParser::with_options("[1, bad, 3]".chars(), options.clone());
+        assert_eq!(parser.parse(), Ok(Array(vec![Num(1.0), Num(3.0)])));
+        assert_eq!(parser.errors(), &[JsonError { reason: UnexpectedCharacter, line: 1, col: 5 }]);
+
+        let mut parser = JsonParser::with_options("[{\"a\": x, \"b\": [1]}, \"],\", [2, ?]]".chars(), options);
+        assert_eq!(parser.parse(), Ok(Array(vec![Str("],".to_string()), Array(vec![Num(2.0)])])));
+        assert_eq!(parser.errors().len(), 2);
+
+        let mut parser = JsonParser::new("[1, bad, 3]".chars());
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn position_after_prefix() {
+        let mut parser = JsonParser::new("[1, 2]\n  rest".chars());
+        assert_eq!(parser.position(), (1, 1));
+        assert_eq!(parser.current_char(), Some('['));
+
+        assert!(parser.parse_partial().is_ok());
+        assert_eq!(parser.position(), (1, 7));
+        assert_eq!(parser.current_char(), Some('\n'));
+
+        parser.consume_whitespace();
+        assert_eq!(parser.position(), (2, 3));
+        assert_eq!(parser.current_char(), Some('r'));
+
+        let mut parser = JsonParser::new("42".chars());
+        assert!(parser.parse_partial().is_ok());
+        assert_eq!(parser.current_char(), None);
+    }
+
+    #[test]
+    fn ndjson_lines() {
+        let value = parse_array_value("[1, \"two\", [3, null]]").unwrap();
+        let ndjson = to_ndjson(&value).unwrap();
+        assert_eq!(ndjson, "1\n\"two\"\n[3,null]\n");
+        assert_eq!(ndjson.lines().count(), 3);
+
+        assert_eq!(to_ndjson(&Num(1.0)).unwrap_err().reason, TypeMismatch);
+    }
+
+    #[test]
+    fn parse_raw_numbers() {
+        let options = ParserOptions { raw_numbers: true, ..ParserOptions::default() };
+        let mut parser = JsonParser::with_options("[3.1400, 2.50, 12345678901234567890123]".chars(), options);
+        let value = parser.parse().unwrap();
+        assert_eq!(value[0], RawNumber("3.1400".to_string()));
+        assert_eq!(value.to_string(), "[3.1400,2.50,12345678901234567890123]");
+        assert_eq!(value[1].as_f64(), Some(2.5));
+        assert_eq!(value[0].type_name(), "number");
+
+        let mut parser = JsonParser::new("3.1400".chars());
+        assert_eq!(parser.parse().unwrap().to_string(), "3.14");
+    }
+
+    #[test]
+    fn iter_all_paths() {
+        let value = parse_object_value("{\"b\": [true, {\"c\": null}], \"a\": 1}").unwrap();
+        let paths: Vec<String> = value.iter_paths().map(|(p, _)| p).collect();
+        assert_eq!(paths, vec!["", "/a", "/b", "/b/0", "/b/1", "/b/1/c"]);
+
+        let (path, node) = value.iter_paths().nth(4).unwrap();
+        assert_eq!(path, "/b/1");
+        assert_eq!(node.type_name(), "object");
+    }
+
+    #[test]
+    fn parse_bool_partial_literal() {
+        for input in &["trun", "fals", "truex", "t", "false_"] {
+            let mut parser = JsonParser::new(input.chars());
+            assert_eq!(parser.parse_bool().unwrap_err().reason, ExpectedBool);
+        }
+        let mut parser = JsonParser::new("nullx".chars());
+        assert_eq!(parser.parse().unwrap_err().reason, ExpectedNull);
+
+        let mut parser = JsonParser::new("[true,false ]".chars());
+        assert_eq!(parser.parse(), Ok(Array(vec![Bool(true), Bool(false)])));
+    }
+
+    #[test]
+    fn intern_shared_strings() {
+        let original = parse_array_value("[\"abc\", {\"k\": \"abc\", \"l\": \"x\"}, \"abc\"]").unwrap();
+        let mut value = original.clone();
+        value.intern_strings();
+
+        let first = match value[0] { SharedStr(ref s) => s.clone(), _ => panic!() };
+        let nested = match value[1]["k"] { SharedStr(ref s) => s.clone(), _ => panic!() };
+        let last = match value[2] { SharedStr(ref s) => s.clone(), _ => panic!() };
+        assert!(Arc::ptr_eq(&first, &nested));
+        assert!(Arc::ptr_eq(&first, &last));
+
+        assert_eq!(value, original);
+        // Rebuilt objects may list their members in another order.
+        assert_eq!(from_str(&value.to_string()), Ok(original.clone()));
+        assert_eq!(value[1]["l"].as_str(), Some("x"));
+
+        let original = parse_array_value(r#"[{"id": 1, "abc": 2}, {"id": 3}, {"x": "id"}]"#).unwrap();
+        let mut value = original.clone();
+        value.intern_strings();
+        let key = |i: usize, k: &str| match value[i] {
+            Object(ref map) => map.keys().find(|key| &***key == k).unwrap().clone(),
+            _ => panic!()
+        };
+        assert!(Arc::ptr_eq(&key(0, "id"), &key(1, "id")));
+        let string = match value[2]["x"] { SharedStr(ref s) => s.clone(), _ => panic!() };
+        assert!(Arc::ptr_eq(&key(0, "id"), &string));
+        assert!(!Arc::ptr_eq(&key(0, "id"), &key(0, "abc")));
+        assert_eq!(value, original);
+        assert_eq!(from_str(&value.to_string()), Ok(original));
+    }
+
+    #[test]
+    fn serde_json_compat_output() {
+        let value = ObjectBuilder::new()
+            .key("b", ArrayBuilder::new()
+                 .push(Num(1.0)).push(Num(2.5)).push(Num(-0.0)).push(Num(1e-7))
+                 .push(Num(0.00001)).push(Num(1e20)).push(Num(-123.456)).push(Num(f64::NAN))
+                 .build())
+            .key("a", Str("x\ny\u{1f}".to_string()))
+            .key("c", ObjectBuilder::new().key("z", Null).key("d", Bool(true)).build())
+            .build();
+        let options = SerializerOptions { serde_json_compat: true, ..SerializerOptions::default() };
+        assert_eq!(to_string_with_options(&value, &options),
+                   "{\"a\":\"x\\ny\\u001f\",\"b\":[1,2.5,-0.0,1e-7,0.00001,1e20,-123.456,null],\
+                    \"c\":{\"d\":true,\"z\":null}}");
+    }
+
+    // Endless stream of spaces that counts how much was read.
+    struct EndlessReader {
+        read: usize
+    }
+
+    impl io::Read for EndlessReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            for b in buf.iter_mut() {
+                *b = b' ';
+            }
+            self.read += buf.len();
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn from_reader_limit() {
+        let mut reader = EndlessReader { read: 0 };
+        let err = from_reader_with_limit(&mut reader, 1024).unwrap_err();
+        assert_eq!(err.reason, LimitExceeded);
+        assert!(reader.read <= 1025);
+
+        assert_eq!(from_reader_with_limit("[1, 2]".as_bytes(), 6),
+                   Ok(Array(vec![Num(1.0), Num(2.0)])));
+        assert_eq!(from_str_with_limit("[1, 2]", 5).unwrap_err().reason, LimitExceeded);
+        assert_eq!(from_reader_with_limit("[1, 2]".as_bytes(), usize::MAX),
+                   Ok(Array(vec![Num(1.0), Num(2.0)])));
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn serde_json_round_trip() {
+        let value = ObjectBuilder::new()
+            .key("a", ArrayBuilder::new().push(Num(1.0)).push(Num(2.5)).push(U64(u64::MAX)).build())
+            .key("b", ObjectBuilder::new().key("c", Str("x".to_string())).key("d", Null).build())
+            .key("e", Bool(false))
+            .build();
+        let converted = serde_json::Value::from(value.clone());
+        assert_eq!(converted, serde_json::json!({
+            "a": [1, 2.5, 18446744073709551615u64],
+            "b": {"c": "x", "d": null},
+            "e": false
+        }));
+        assert_eq!(JsonValue::from(converted), value);
+    }
+
+    #[test]
+    fn type_histogram() {
+        let value = JsonParser::new(r#"{"a": [1, 2, "x", null], "b": {"c": true, "d": 3.5}}"#.chars())
+            .parse().unwrap();
+        let counts = value.type_histogram();
+        assert_eq!(counts["object"], 2);
+        assert_eq!(counts["array"], 1);
+        assert_eq!(counts["number"], 3);
+        assert_eq!(counts["string"], 1);
+        assert_eq!(counts["null"], 1);
+        assert_eq!(counts["bool"], 1);
+        assert_eq!(counts.len(), 6);
+    }
+
+    #[cfg(feature = "btree_map")]
+    #[test]
+    fn btree_map_sorted_output() {
+        let value = JsonParser::new(r#"{"c": 1, "a": {"z": null, "b": 2}, "b": true}"#.chars())
+            .parse().unwrap();
+        assert_eq!(print_json(&value), r#"{"a":{"b":2,"z":null},"b":true,"c":1}"#);
+        assert_eq!(value.find("a").and_then(|a| a.find("b")), Some(&Num(2.0)));
+    }
+
+    #[test]
+    fn redact() {
+        let mut value = JsonParser::new(
+            r#"{"name": "bob", "password": "hunter2", "user": {"id": 4, "token": [1, 2]}}"#.chars())
+            .parse().unwrap();
+        value.redact(&["/password", "/user/token", "/missing", "/name/x"]);
+        let expected = JsonParser::new(
+            r#"{"name": "bob", "password": "***", "user": {"id": 4, "token": "***"}}"#.chars())
+            .parse().unwrap();
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn parse_string_escapes() {
+        let mut parser = JsonParser::new(r#""a\"b\\c\n\u00e9\ud83d\ude00""#.chars());
+        assert_eq!(parser.parse(), Ok(Str("a\"b\\c\n\u{e9}\u{1f600}".to_string())));
+
+        for input in &[r#""\x""#, r#""\u12g4""#, r#""\ud83d""#, r#""\ud83d\u0041""#] {
+            let mut parser = JsonParser::new(input.chars());
+            assert_eq!(parser.parse().unwrap_err().reason, InvalidEscape);
+        }
+    }
+
+    #[test]
+    fn forward_slash_escape() {
+        let escaped = JsonParser::new(r#""a\/b""#.chars()).parse().unwrap();
+        let plain = JsonParser::new(r#""a/b""#.chars()).parse().unwrap();
+        assert_eq!(escaped, Str("a/b".to_string()));
+        assert_eq!(escaped, plain);
+        assert_eq!(print_json(&escaped), r#""a/b""#);
+
+        let options = SerializerOptions { escape_forward_slashes: true, ..SerializerOptions::default() };
+        assert_eq!(to_string_with_options(&escaped, &options), r#""a\/b""#);
+    }
+
+    #[test]
+    fn coerce_values() {
+        assert_eq!(Str(" 42 ".to_string()).coerce_to_number(), Some(42.0));
+        assert_eq!(Bool(true).coerce_to_number(), Some(1.0));
+        assert_eq!(Bool(false).coerce_to_number(), Some(0.0));
+        assert_eq!(Num(2.5).coerce_to_number(), Some(2.5));
+        assert_eq!(Str("abc".to_string()).coerce_to_number(), None);
+        assert_eq!(Str("NaN".to_string()).coerce_to_number(), None);
+        assert_eq!(Null.coerce_to_number(), None);
+
+        assert_eq!(Str("a b".to_string()).coerce_to_string(), "a b");
+        assert_eq!(Num(1.5).coerce_to_string(), "1.5");
+        assert_eq!(Null.coerce_to_string(), "null");
+        assert_eq!(Array(vec![Num(1.0), Bool(true)]).coerce_to_string(), "[1,true]");
+    }
+
+    #[test]
+    fn negative_zero() {
+        for input in &["-0", "-0.0", "-0e5"] {
+            let value = JsonParser::new(input.chars()).parse().unwrap();
+            match value {
+                Num(n) => assert!(n == 0.0 && n.is_sign_negative()),
+                _ => panic!("{:?}", value)
+            }
+            assert_eq!(value, Num(0.0));
+            assert_eq!(print_json(&value), "-0");
+            assert_eq!(to_pretty_string(&value, 2), "-0");
+        }
+        assert_eq!(print_json(&JsonParser::new("0".chars()).parse().unwrap()), "0");
+    }
+
+    #[test]
+    fn array_stream_elements() {
+        let mut stream = array_stream(" [1, {\"a\": true},3] ");
+        assert_eq!(stream.next(), Some(Ok(Num(1.0))));
+        assert_eq!(stream.next().unwrap().unwrap().find("a"), Some(&Bool(true)));
+        assert_eq!(stream.next(), Some(Ok(Num(3.0))));
+        assert_eq!(stream.next(), None);
+
+        assert_eq!(array_stream("[]").count(), 0);
+        let results: Vec<_> = array_stream("[1, 2").collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[2].as_ref().unwrap_err().reason, UnclosedArray);
+        assert_eq!(array_stream("{}").next().unwrap().unwrap_err().reason, UnexpectedCharacter);
+    }
+
+    #[test]
+    fn display_alternate() {
+        let value = ObjectBuilder::new().key("a", Array(vec![Num(1.0), Num(2.0)])).build();
+        assert_eq!(format!("{}", value), r#"{"a":[1,2]}"#);
+        assert_eq!(format!("{:#}", value), to_pretty_string(&value, 2));
+        assert!(format!("{:#}", value).lines().count() > 1);
+    }
+
+    #[test]
+    fn as_i64_checked() {
+        assert_eq!(Num(2.0).as_i64_checked(), Some(2));
+        assert_eq!(Num(-7.0).as_i64_checked(), Some(-7));
+        assert_eq!(Num(2.5).as_i64_checked(), None);
+        assert_eq!(Num(1e30).as_i64_checked(), None);
+        assert_eq!(Num(f64::NAN).as_i64_checked(), None);
+        assert_eq!(U64(u64::MAX).as_i64_checked(), None);
+        assert_eq!(Str("2".to_string()).as_i64_checked(), None);
+    }
+
+    #[test]
+    fn serialized_len() {
+        let values = vec![
+            Null,
+            Bool(false),
+            Num(-1.25),
+            U64(u64::MAX),
+            Str("tab\there \"quoted\" \u{1} é".to_string()),
+            Array(vec![]),
+            JsonParser::new(r#"{"a\nb": [1, true, {"c": null}], "d": "x"}"#.chars()).parse().unwrap()
+        ];
+        for value in &values {
+            assert_eq!(value.serialized_len(), value.to_string().len(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn extra_whitespace() {
+        let input = "{\x0c\"a\"\x0b:\x0b[1,\x0c2]}";
+        assert!(JsonParser::new(input.chars()).parse().is_err());
+
+        let options = ParserOptions { extra_whitespace: vec!['\x0b', '\x0c'], ..ParserOptions::default() };
+        let result = JsonParser::with_options(input.chars(), options).parse().unwrap();
+        assert_eq!(result.find("a"), Some(&Array(vec![Num(1.0), Num(2.0)])));
+    }
+
+    #[test]
+    fn all_keys() {
+        let value = JsonParser::new(
+            r#"{"b": [{"id": 1, "name": "x"}, {"id": 2}], "a": {"nested": {"id": 3}}, "c": "id"}"#.chars())
+            .parse().unwrap();
+        let keys: Vec<String> = value.all_keys().into_iter().collect();
+        assert_eq!(keys, vec!["a", "b", "c", "id", "name", "nested"]);
+        assert!(Num(1.0).all_keys().is_empty());
+    }
+
+    #[test]
+    fn remove_pointer() {
+        let mut value = JsonParser::new(r#"{"a": {"b": 1, "c": 2}, "list": [10, 20, 30]}"#.chars())
+            .parse().unwrap();
+        assert_eq!(value.remove_pointer("/a/b"), Some(Num(1.0)));
+        assert_eq!(value.remove_pointer("/list/0"), Some(Num(10.0)));
+        assert_eq!(value.pointer("/list"), Some(&Array(vec![Num(20.0), Num(30.0)])));
+        assert_eq!(value.pointer("/a"), Some(&ObjectBuilder::new().key("c", Num(2.0)).build()));
+
+        assert_eq!(value.remove_pointer("/a/b"), None);
+        assert_eq!(value.remove_pointer("/list/2"), None);
+        assert_eq!(value.remove_pointer("/a/c/d"), None);
+        assert_eq!(value.remove_pointer(""), None);
+    }
+
+    #[test]
+    fn normalize_numbers() {
+        let options = ParserOptions { raw_numbers: true, ..ParserOptions::default() };
+        let mut raw = JsonParser::with_options("[2, 2.0, -0, 1e19, 18446744073709551615]".chars(), options)
+            .parse().unwrap();
+        let mut built = Array(vec![Num(2.0), Num(2.0), Num(-0.0), Num(1e19), U64(u64::MAX)]);
+        assert!(raw != built);
+
+        raw.normalize_numbers();
+        built.normalize_numbers();
+        assert_eq!(raw, built);
+        assert_eq!(print_json(&built), "[2,2,0,10000000000000000000,18446744073709551615]");
+    }
+
+    #[test]
+    fn single_quoted_strings() {
+        let input = r#"{'greeting': 'hello', "quote": 'it\'s "fine"'}"#;
+        assert_eq!(JsonParser::new(input.chars()).parse().unwrap_err().reason, UnclosedStringLiteral);
+        assert_eq!(JsonParser::new("'hello'".chars()).parse().unwrap_err().reason, UnexpectedCharacter);
+
+        let options = ParserOptions { allow_single_quotes: true, ..ParserOptions::default() };
+        let result = JsonParser::with_options(input.chars(), options.clone()).parse().unwrap();
+        assert_eq!(result.find("greeting"), Some(&Str("hello".to_string())));
+        assert_eq!(result.find("quote"), Some(&Str("it's \"fine\"".to_string())));
+        assert_eq!(JsonParser::with_options("'hello'".chars(), options).parse(),
+                   Ok(Str("hello".to_string())));
+    }
+
+    #[test]
+    fn unquoted_keys() {
+        let input = "{name: 1, _value2 : 2, \"quoted\": 3}";
+        assert_eq!(JsonParser::new(input.chars()).parse().unwrap_err().reason, UnclosedStringLiteral);
+
+        let options = ParserOptions { allow_unquoted_keys: true, ..ParserOptions::default() };
+        let result = JsonParser::with_options(input.chars(), options.clone()).parse().unwrap();
+        assert_eq!(result.find("name"), Some(&Num(1.0)));
+        assert_eq!(result.find("_value2"), Some(&Num(2.0)));
+        assert_eq!(result.find("quoted"), Some(&Num(3.0)));
+        assert!(JsonParser::with_options("{2x: 1}".chars(), options).parse().is_err());
+    }
+
+    #[test]
+    fn content_hash() {
+        let a = JsonParser::new(r#"{"x": [1, 2.5], "y": {"p": null, "q": "s"}}"#.chars()).parse().unwrap();
+        let options = ParserOptions { raw_numbers: true, ..ParserOptions::default() };
+        let b = JsonParser::with_options(r#"{"y": {"q": "s", "p": null}, "x": [1.0, 2.50]}"#.chars(), options)
+            .parse().unwrap();
+        let c = JsonParser::new(r#"{"x": [1, 2.5], "y": {"p": null, "q": "t"}}"#.chars()).parse().unwrap();
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert!(a.content_hash() != c.content_hash());
+
+        let hashes: Vec<u64> = [Null, Num(f64::NAN), Num(f64::INFINITY), Num(f64::NEG_INFINITY)].iter()
+            .map(|v| Array(vec![v.clone()]).content_hash())
+            .collect();
+        for (i, h) in hashes.iter().enumerate() {
+            assert!(!hashes[i + 1..].contains(h));
+        }
+    }
+
+    // Produces `[0,1,2,...]` with `count` elements on demand and
+    // records the largest read request.
+    struct GeneratedArray {
+        count: usize,
+        next: usize,
+        pending: Vec<u8>,
+        max_request: usize
+    }
+
+    impl io::Read for GeneratedArray {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.max_request = std::cmp::max(self.max_request, buf.len());
+            while self.pending.len() < buf.len() && self.next <= self.count {
+                let chunk = match self.next {
+                    0 => "[0".to_string(),
+                    n if n == self.count => "]".to_string(),
+                    n => format!(",{}", n)
+                };
+                self.pending.extend_from_slice(chunk.as_bytes());
+                self.next += 1;
+            }
+            let n = std::cmp::min(buf.len(), self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn from_reader_streaming() {
+        let mut reader = GeneratedArray { count: 200_000, next: 0, pending: Vec::new(), max_request: 0 };
+        let result = from_reader(&mut reader).unwrap().into_array().unwrap();
+        assert_eq!(result.len(), 200_000);
+        assert_eq!(result[199_999], Num(199_999.0));
+        // The input went through a small buffer, never all at once.
+        assert!(reader.max_request <= 64 * 1024);
+
+        assert_eq!(from_reader("[\"\u{e9}\u{1f600}\"]".as_bytes()),
+                   Ok(Array(vec![Str("\u{e9}\u{1f600}".to_string())])));
+        let err = from_reader(&b"\"a\xffb\xc3\""[..]).unwrap_err();
+        assert_eq!((err.reason, err.line, err.col), (InvalidUtf8, 1, 3));
+        let err = from_reader(&b"[\"\xc3\"]"[..]).unwrap_err();
+        assert_eq!((err.reason, err.line, err.col), (InvalidUtf8, 1, 3));
+        assert_eq!(from_reader(&b"[1] \xf0\x9f"[..]).unwrap_err().reason, InvalidUtf8);
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    // Runs the CLI, checks that it printed `expected` and
+    // returns the output.
+    fn run_cli(cli_args: &[&str], expected: &JsonValue) -> String {
+        let mut out = Vec::new();
+        run(&args(cli_args), io::empty(), &mut out).unwrap();
+        let output = String::from_utf8(out).unwrap();
+        assert_eq!(&JsonParser::new(output.chars()).parse().unwrap(), expected);
+        output
+    }
+
+    #[test]
+    fn cli_pretty() {
+        let expected = JsonParser::new(include_str!("../test.json").chars()).parse().unwrap();
+
+        let output = run_cli(&["--pretty", "test.json"], &expected);
+        assert!(output.lines().nth(1).unwrap().starts_with("  \"glossary\""));
+        let output = run_cli(&["test.json", "--indent", "4"], &expected);
+        assert!(output.lines().nth(1).unwrap().starts_with("    \"glossary\""));
+        let output = run_cli(&["--compact", "test.json"], &expected);
+        assert_eq!(output.lines().count(), 1);
+
+        let err = run(&args(&["--shiny", "test.json"]), io::empty(), &mut Vec::new()).unwrap_err();
+        assert!(err.starts_with("unknown flag --shiny\nusage:"));
+    }
+
+    #[test]
+    fn cli_unknown_short_flag() {
+        let err = run(&args(&["-x", "test.json"]), io::empty(), &mut Vec::new()).unwrap_err();
+        assert!(err.starts_with("unknown flag -x\nusage:"));
+        assert_eq!(parse_args(&args(&["-"])).unwrap().path, None);
+    }
+
+    #[test]
+    fn cli_validate() {
+        let mut out = Vec::new();
+        run(&args(&["--validate", "test.json"]), io::empty(), &mut out).unwrap();
+        assert!(out.is_empty());
+
+        let path = std::env::temp_dir().join("json-rs-cli-validate.json");
+        std::fs::write(&path, "{\"a\": [1, 2}\n").unwrap();
+        let err = run(&args(&["--validate", path.to_str().unwrap()]), io::empty(), &mut out).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err, "1:12 error: Unexpected character");
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn cli_stdin() {
+        for cli_args in &[&[][..], &["-"][..], &["--compact", "-"][..]] {
+            let mut out = Vec::new();
+            run(&args(cli_args), "{\"a\": [1, true]}".as_bytes(), &mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), "{\"a\":[1,true]}\n");
+        }
+        assert!(run(&args(&["-", "test.json"]), io::empty(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn jsonpath_query() {
+        let value = JsonParser::new(r#"{
+            "id": 0,
+            "users": [{"id": 1, "name": "ann"}, {"id": 2, "name": "bob", "pet": {"id": 3}}],
+            "meta": {"count": 2}
+        }"#.chars()).parse().unwrap();
+
+        let names = value.query("$.users[*].name").unwrap();
+        assert_eq!(names, vec![&Str("ann".to_string()), &Str("bob".to_string())]);
+
+        let mut ids: Vec<f64> = value.query("$..id").unwrap().iter().filter_map(|v| v.as_f64()).collect();
+        ids.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(ids, vec![0.0, 1.0, 2.0, 3.0]);
+
+        assert_eq!(value.query("$.users[1]['pet'].id").unwrap(), vec![&Num(3.0)]);
+        assert_eq!(value.query("$").unwrap(), vec![&value]);
+        assert_eq!(value.query("$.users[5].name").unwrap().len(), 0);
+        assert_eq!(value.query("$.meta.*").unwrap(), vec![&Num(2.0)]);
+        for expr in &["users", "$.users[", "$.users[x]", "$.", "$users"] {
+            assert_eq!(value.query(expr).unwrap_err().reason, InvalidQuery, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn truncate_depth() {
+        let value = JsonParser::new(r#"{"a": {"b": {"c": {"d": {"e": 1}}}, "n": 2}, "l": [[1], 3]}"#.chars())
+            .parse().unwrap();
+        let expected = JsonParser::new(r#"{"a": {"b": "...", "n": 2}, "l": ["...", 3]}"#.chars())
+            .parse().unwrap();
+        assert_eq!(value.truncate_depth(2), expected);
+        assert_eq!(value.truncate_depth(5), value);
+        assert_eq!(value.truncate_depth(0), Str("...".to_string()));
+        assert_eq!(Num(1.0).truncate_depth(0), Num(1.0));
+    }
+
+    #[test]
+    fn string_values() {
+        let value = JsonParser::new(r#"["a", {"key": ["b", 1, {"c": "d"}]}, null, "e"]"#.chars())
+            .parse().unwrap();
+        assert_eq!(value.string_values(), vec!["a", "b", "d", "e"]);
+        assert!(Num(1.0).string_values().is_empty());
+    }
+
+    #[test]
+    fn parse_num_boundaries() {
+        let mut parser = JsonParser::new("42".chars());
+        assert_eq!(parser.parse_num(), Ok(Num(42.0)));
+        assert!(parser.eof());
+        assert_eq!(parser.position(), (1, 3));
+
+        let mut parser = JsonParser::new("42 ".chars());
+        assert_eq!(parser.parse(), Ok(Num(42.0)));
+        assert!(parser.eof());
+
+        let mut parser = JsonParser::new("42]".chars());
+        assert_eq!(parser.parse_num(), Ok(Num(42.0)));
+        assert_eq!(parser.current_char(), Some(']'));
+        assert_eq!(parser.position(), (1, 3));
+
+        let mut parser = JsonParser::new("[1, 42]".chars());
+        assert_eq!(parser.parse(), Ok(Array(vec![Num(1.0), Num(42.0)])));
+        assert!(parser.eof());
+        assert_eq!(JsonParser::new("42x".chars()).parse().unwrap_err().reason, TrailingCharacters);
+    }
+
+    #[test]
+    fn parse_decimal_numbers() {
+        let options = ParserOptions { decimal_numbers: true, ..ParserOptions::default() };
+        let value = JsonParser::with_options("[0.1, 0.2, 0.30, -1.5e-3, 12e2, 7]".chars(), options.clone())
+            .parse().unwrap();
+        let nums: Vec<Decimal> = value.into_array().unwrap().into_iter()
+            .map(|v| match v { Dec(d) => d, v => panic!("{:?}", v) })
+            .collect();
+        assert_eq!(nums[0] + nums[1], nums[2]);
+        assert_eq!((nums[0] + nums[1]).to_string(), "0.3");
+        assert_eq!(nums[3].to_string(), "-0.0015");
+        assert_eq!(nums[4].to_string(), "1200");
+        assert_eq!(print_json(&Array(vec![Dec(nums[3]), Dec(nums[5])])), "[-0.0015,7]");
+        assert_eq!(Dec(nums[5]).as_u64(), Some(7));
+
+        let huge = JsonParser::with_options("1e40".chars(), options).parse();
+        assert_eq!(huge.unwrap_err().reason, NumberParsing);
+    }
+
+    #[test]
+    fn sorted_entries() {
+        let value = JsonParser::new(r#"{"zeta": 1, "alpha": 2, "mid": 3, "Beta": 4}"#.chars()).parse().unwrap();
+        let keys: Vec<&str> = value.sorted_entries().iter().map(|&(k, _)| &**k).collect();
+        assert_eq!(keys, vec!["Beta", "alpha", "mid", "zeta"]);
+        assert_eq!(value.sorted_entries()[0].1, &Num(4.0));
+        assert!(Array(vec![Null]).sorted_entries().is_empty());
+    }
+
+    #[test]
+    fn parse_into_arena() {
+        let input = r#"{"name": "widget", "tags": ["a", "b"], "size": {"w": 2.5, "h": 4}, "ok": true, "none": null}"#;
+        let mut arena = Arena::new();
+        {
+            let root = arena.parse(input).unwrap();
+            assert_eq!(root.get("name").and_then(|v| v.as_str()), Some("widget"));
+            let tags = root.get("tags").unwrap();
+            assert_eq!(tags.len(), 2);
+            assert_eq!(tags.at(1).and_then(|v| v.as_str()), Some("b"));
+            assert!(tags.at(2).is_none());
+            assert_eq!(root.get("size").and_then(|v| v.get("w")).and_then(|v| v.as_f64()), Some(2.5));
+            assert_eq!(root.get("ok").and_then(|v| v.as_bool()), Some(true));
+            assert!(root.get("none").unwrap().is_null());
+            assert_eq!(root.to_value(), JsonParser::new(input.chars()).parse().unwrap());
+        }
+
+        arena.clear();
+        let root = arena.parse("[[], {}, [1, [2]]]").unwrap();
+        assert!(root.at(0).unwrap().is_empty());
+        assert_eq!(root.at(2).and_then(|v| v.at(1)).and_then(|v| v.at(0)).and_then(|v| v.as_f64()), Some(2.0));
+        assert_eq!(arena.parse("[1, 2").unwrap_err().reason, UnclosedArray);
+        assert_eq!(arena.parse(r#"["a\tb", {"k\u00e9y": -1.5e2}]"#).unwrap().to_value(),
+                   from_str(r#"["a\tb", {"k\u00e9y": -1.5e2}]"#).unwrap());
+    }
+
+    #[test]
+    fn arena_errors_match_parser() {
+        let inputs = ["[", "[1,", " [1 ", "{", "{\"a\"", "{\"a\":", "{\"a\" 1}", "{1: 2}", "[1 2]",
+                      "[tru]", "[-]", "[1.e]", "\"abc", "[\"a\\q\"]", "[1,]", "{\"a\": 1,}", "", "1 2"];
+        for input in &inputs {
+            let expected = JsonParser::new(input.chars()).parse().unwrap_err();
+            let err = Arena::new().parse(input).map(|_| ()).unwrap_err();
+            assert_eq!((err.reason, err.line, err.col), (expected.reason, expected.line, expected.col), "{}", input);
+        }
+    }
+
+    #[test]
+    fn same_shape() {
+        let parse = |s: &str| JsonParser::new(s.chars()).parse().unwrap();
+        let a = parse(r#"{"id": 1, "name": "a", "tags": ["x", "y"], "geo": {"lat": 1.5}}"#);
+        let b = parse(r#"{"id": 2, "name": "b", "tags": ["z"], "geo": {"lat": -3}}"#);
+        let c = parse(r#"{"id": 2, "title": "b", "tags": ["z"], "geo": {"lat": -3}}"#);
+        let d = parse(r#"{"id": "2", "name": "b", "tags": ["z"], "geo": {"lat": -3}}"#);
+        assert!(a.same_shape(&b));
+        assert!(!a.same_shape_with(&b, true));
+        assert!(!a.same_shape(&c));
+        assert!(!a.same_shape(&d));
+        assert!(a.same_shape_with(&a, true));
+    }
+
+    #[test]
+    fn push_parser_chunks() {
+        let input = r#"{"text": "a \"quoted\" ]} string", "nums": [12345, -6.5e3], "ok": true}"#;
+        let expected = JsonParser::new(input.chars()).parse().unwrap();
+        for split in 1..input.len() {
+            let mut parser = PushParser::new();
+            for chunk in input.as_bytes().chunks(split) {
+                parser.feed(str::from_utf8(chunk).unwrap()).unwrap();
+            }
+            assert!(parser.is_complete());
+            assert_eq!(parser.finish(), Ok(expected.clone()));
+        }
+
+        let mut parser = PushParser::new();
+        parser.feed("[1, {\"a\": ").unwrap();
+        assert!(!parser.is_complete());
+        parser.feed("2}").unwrap();
+        assert!(!parser.is_complete());
+        assert_eq!(parser.finish().unwrap_err().reason, UnclosedArray);
+    }
+
+    #[test]
+    fn paths_to() {
+        let value = JsonParser::new(
+            r#"{"owner": "ann", "items": [{"by": "bob"}, {"by": "ann", "tags": ["ann"]}], "a/b": "ann"}"#.chars())
+            .parse().unwrap();
+        assert_eq!(value.paths_to(&Str("ann".to_string())),
+                   vec!["/a~1b", "/items/1/by", "/items/1/tags/0", "/owner"]);
+        assert_eq!(value.paths_to(&value), vec![""]);
+        assert!(value.paths_to(&Null).is_empty());
+    }
+
+    #[test]
+    fn from_dotted_map() {
+        let mut map = HashMap::new();
+        map.insert("db.host".to_string(), Str("x".to_string()));
+        map.insert("db.port".to_string(), Str("5432".to_string()));
+        map.insert("db.pool.max".to_string(), Num(10.0));
+        map.insert("debug".to_string(), Bool(true));
+        let expected = JsonParser::new(
+            r#"{"db": {"host": "x", "port": "5432", "pool": {"max": 10}}, "debug": true}"#.chars())
+            .parse().unwrap();
+        assert_eq!(JsonValue::from_dotted_map(map.clone()), Ok(expected));
+
+        map.insert("db.host.name".to_string(), Null);
+        assert_eq!(JsonValue::from_dotted_map(map).unwrap_err().reason, PathConflict);
+    }
+
+    #[test]
+    fn sample_wide_values() {
+        let mut builder = ObjectBuilder::new();
+        for i in 0..10 {
+            builder = builder.key(&format!("k{}", i), Array((0..5).map(|n| Num(n as f64)).collect()));
+        }
+        let sampled = builder.build().sample(3, 2);
+        let expected = JsonParser::new(
+            r#"{"k0": [0, 1, "... 3 more"], "k1": [0, 1, "... 3 more"], "k2": [0, 1, "... 3 more"],
+                "...": "7 more"}"#.chars())
+            .parse().unwrap();
+        assert_eq!(sampled, expected);
+        assert_eq!(Array(vec![Null]).sample(3, 2), Array(vec![Null]));
+    }
+
+    #[test]
+    fn round_floats() {
+        let parse = |s: &str| JsonParser::new(s.chars()).parse().unwrap();
+        let value = parse(r#"[3.14159, {"m": [2.71828, -0.005, 10]}, "1.2345"]"#);
+        assert_eq!(value.round_floats(2), parse(r#"[3.14, {"m": [2.72, -0.01, 10]}, "1.2345"]"#));
+        assert_eq!(value.round_floats(0), parse(r#"[3, {"m": [3, -0, 10]}, "1.2345"]"#));
+    }
+
+    #[test]
+    fn parse_keeping_partial() {
+        let parse = |s: &str| JsonParser::new(s.chars()).parse().unwrap();
+        let mut parser = JsonParser::new("[1, \"two\", [3, 4], {\"a\": [5, ".chars());
+        let (partial, err) = parser.parse_keeping_partial().unwrap_err();
+        assert_eq!(err.reason, UnclosedArray);
+        assert_eq!(partial, Some(parse(r#"[1, "two", [3, 4], {"a": [5]}]"#)));
+
+        let mut parser = JsonParser::new("{\"x\": 1, \"y\": tru".chars());
+        let (partial, err) = parser.parse_keeping_partial().unwrap_err();
+        assert_eq!(err.reason, ExpectedBool);
+        assert_eq!(partial, Some(parse(r#"{"x": 1}"#)));
+
+        let mut parser = JsonParser::new("nul".chars());
+        assert_eq!(parser.parse_keeping_partial().unwrap_err().0, None);
+        let mut parser = JsonParser::new("null x".chars());
+        assert_eq!(parser.parse_keeping_partial().unwrap_err().0, Some(Null));
+        let mut parser = JsonParser::new("[1] x".chars());
+        assert_eq!(parser.parse_keeping_partial().unwrap_err().0, Some(Array(vec![Num(1.0)])));
+        let mut parser = JsonParser::new("[1]".chars());
+        assert_eq!(parser.parse_keeping_partial(), Ok(Array(vec![Num(1.0)])));
+    }
+
+    #[test]
+    fn array_contains() {
+        let value = JsonParser::new(r#"[1, "a", {"k": [true]}]"#.chars()).parse().unwrap();
+        assert!(value.array_contains(&Num(1.0)));
+        assert!(value.array_contains(&ObjectBuilder::new().key("k", Array(vec![Bool(true)])).build()));
+        assert!(!value.array_contains(&Num(2.0)));
+        assert!(!value.array_contains(&Bool(true)));
+        assert!(!Num(1.0).array_contains(&Num(1.0)));
+    }
+
+    #[test]
+    fn parse_json5_lite() {
+        let config = r#"
+            // Server settings
+            {
+                name: 'my app',
+                port: 8080, /* default port */
+                "hosts": ['a.example', "b.example",],
+                tls: {enabled: true, cert: 'it\'s.pem',},
+            }
+        "#;
+        let expected = JsonParser::new(r#"{
+            "name": "my app",
+            "port": 8080,
+            "hosts": ["a.example", "b.example"],
+            "tls": {"enabled": true, "cert": "it's.pem"}
+        }"#.chars()).parse().unwrap();
+        assert!(JsonParser::new(config.chars()).parse().is_err());
+        assert_eq!(JsonParser::with_options(config.chars(), ParserOptions::json5_lite()).parse(), Ok(expected));
+
+        let options = ParserOptions { allow_trailing_commas: true, ..ParserOptions::default() };
+        assert!(JsonParser::with_options("[1,,]".chars(), options.clone()).parse().is_err());
+        assert!(JsonParser::with_options("{,}".chars(), options).parse().is_err());
+    }
 
-    // Parses a JSON boolean.
-    fn parse_bool(&mut self) -> JsonResult {
-        self.consume_whitespace();
-        
-        if self.ch_is('f') {
-            self.consume_text("false");
-            return Ok(Bool(false));
-        }
-        if self.ch_is('t')  {
-            self.consume_text("true");
-            return Ok(Bool(true));
-        }
-        else {
-            self.error(ExpectedBool)
-        }   
+    #[test]
+    fn escape_unescape_strings() {
+        let original = "say \"hi\" \\ path\ttab \u{e9} \u{1f600}";
+        let escaped = escape_json_string(original);
+        assert_eq!(escaped, "say \\\"hi\\\" \\\\ path\\ttab \u{e9} \u{1f600}");
+        assert_eq!(unescape_json_string(&escaped), Ok(original.to_string()));
+        assert_eq!(unescape_json_string("caf\\u00e9 \\ud83d\\ude00 \\/"), Ok("caf\u{e9} \u{1f600} /".to_string()));
+        assert_eq!(unescape_json_string("bad \\q").unwrap_err().reason, InvalidEscape);
+        assert_eq!(unescape_json_string("\u{feff}x"), Ok("\u{feff}x".to_string()));
     }
-    // Parses any JSON value, this is the entry point
-    // for the parser. Tries each possible parse until
-    // one fits. If there are no suitable parses,
-    // returns the most recent error. Error handling
-    // this way isn't exacly ideal because the most recent
-    // error is not always the most fitting one.
-    fn parse_value(&mut self) -> JsonResult {        
-        let p = vec![self.parse_bool(),
-                     self.parse_string(),
-                     self.parse_num(),
-                     self.parse_null(),
-                     self.parse_array(),
-                     self.parse_object()];
-        let mut most_recent_error: Option<JsonError> = None;
-        for result in p {
-            match result {
-                r @ Ok(_) => return r,
-                Err(e) => most_recent_error = Some(e)
-            }
-        }
-        
-        Err(most_recent_error.expect("Bug!"))
+
+    #[test]
+    fn parse_strict_integers() {
+        let input = "999999999999999999999999";
+        assert_eq!(JsonParser::new(input.chars()).parse(), Ok(Num(1e24)));
+
+        let options = ParserOptions { strict_integers: true, ..ParserOptions::default() };
+        let parse = |s: &str| JsonParser::with_options(s.chars(), options.clone()).parse();
+        assert_eq!(parse(input).unwrap_err().reason, NumberOutOfRange);
+        assert_eq!(parse("-9223372036854775809").unwrap_err().reason, NumberOutOfRange);
+        assert_eq!(parse("18446744073709551615"), Ok(U64(u64::MAX)));
+        assert_eq!(parse("-9223372036854775808"), Ok(Num(-9223372036854775808.0)));
+        assert_eq!(parse("1e30"), Ok(Num(1e30)));
+        assert_eq!(parse("[-]").unwrap_err().reason, NumberParsing);
+        assert_eq!(parse("-").unwrap_err().reason, NumberParsing);
     }
-    
-    // Parses a JSON array of values. Example: [true, false, 1, "hello"]
-    fn parse_array(&mut self) -> JsonResult {
-        if self.ch_is('[') {
-            // Consume the opening bracket
-            self.consume_char();
-            let mut array = Vec::new();
-            
-            loop {
-                let value = self.parse_value();
-                match value {
-                    Ok(v) => array.push(v),
-                    e @ Err(_) => return e
-                }
-                // Parse the next value in the array
-                if self.ch_is(',') {
-                    self.consume_char();
-                    continue;
-                }
-                // Reached the end of the array, return it
-                if self.ch_is(']') {
-                    self.consume_char();
-                    return Ok(Array(array));
-                }
-            }
+
+    #[test]
+    fn pretty_print_tabs() {
+        let value = ObjectBuilder::new().key("a", Array(vec![Num(1.0), Array(vec![Null])])).build();
+        assert_eq!(to_pretty_string_with_indent(&value, Indent::Tabs),
+                   "{\n\t\"a\": [\n\t\t1,\n\t\t[\n\t\t\tnull\n\t\t]\n\t]\n}");
+        assert_eq!(to_pretty_string_with_indent(&value, Indent::Spaces(3)), to_pretty_string(&value, 3));
+    }
+
+    #[test]
+    fn merge_arrays() {
+        let base = from_str(r#"{"tags": ["a"], "meta": {"ids": [1], "name": "x"}}"#).unwrap();
+        let other = from_str(r#"{"tags": ["b"], "meta": {"ids": [2]}, "extra": true}"#).unwrap();
+
+        let mut replaced = base.clone();
+        replaced.merge_with(other.clone(), ArrayMergeStrategy::Replace);
+        assert_eq!(replaced, from_str(r#"{"tags": ["b"], "meta": {"ids": [2], "name": "x"}, "extra": true}"#).unwrap());
+
+        let mut concatenated = base;
+        concatenated.merge_with(other, ArrayMergeStrategy::Concat);
+        assert_eq!(concatenated,
+                   from_str(r#"{"tags": ["a", "b"], "meta": {"ids": [1, 2], "name": "x"}, "extra": true}"#).unwrap());
+    }
+
+    #[test]
+    fn parse_skip_shebang() {
+        let input = "#!/usr/bin/env config-tool\n{\"a\": [1]}";
+        let options = ParserOptions { skip_shebang: true, ..ParserOptions::default() };
+        let value = JsonParser::with_options(input.chars(), options.clone()).parse().unwrap();
+        assert_eq!(value, ObjectBuilder::new().key("a", Array(vec![Num(1.0)])).build());
+        assert!(from_str(input).is_err());
+
+        let err = JsonParser::with_options("#{}".chars(), options).parse().unwrap_err();
+        assert_eq!((err.reason, err.line, err.col), (UnexpectedCharacter, 1, 1));
+    }
+
+    #[test]
+    fn error_with_context() {
+        let source = "{\n  \"a\": 1,\n  \"b\" 2\n}";
+        let err = from_str(source).unwrap_err();
+        let message = err.error_with_context(source);
+        let lines: Vec<&str> = message.lines().collect();
+        assert_eq!(lines, vec![&err.to_string()[..], "  \"b\" 2", "      ^"]);
+        assert_eq!(JsonError::new(TypeMismatch).error_with_context(source),
+                   JsonError::new(TypeMismatch).to_string());
+    }
+
+    #[test]
+    fn parse_number_edge_cases() {
+        let cases = [
+            ("1E5", 1e5),
+            ("1e+5", 1e5),
+            ("1e-5", 1e-5),
+            ("-1.5E-10", -1.5e-10),
+            ("0.0", 0.0),
+            ("-0.25e+2", -25.0)
+        ];
+        for &(input, expected) in cases.iter() {
+            assert_eq!(from_str(input), Ok(Num(expected)), "{}", input);
         }
-        else {
-            self.error(UnclosedArray)
+        for input in ["1e", "1E+", "1e5e5", "--1"].iter() {
+            assert_eq!(from_str(input).map_err(|e| e.reason), Err(NumberParsing), "{}", input);
         }
+
+        let mut parser = JsonParser::new("1-2".chars());
+        assert_eq!(parser.parse_partial(), Ok(Num(1.0)));
+        assert_eq!(parser.current_char(), Some('-'));
     }
-    // Parses a JSON object. Example: {"key": [1, 2, 3]}
-    fn parse_object(&mut self) -> JsonResult {
-        if self.eof() {
-            return self.error(EndOfFile);
-        }
-        self.consume_whitespace();
-        if self.ch_is('{') {
-            let mut object = HashMap::new();
-            self.consume_char();
-            loop {
-                self.consume_whitespace();
-                // The key is always a string value.
-                let key = self.parse_string();
-                let key_string = match key {
-                    Ok(s) => s.into_string().unwrap(),
-                    e @ Err(_) => return e
-                };
 
-                self.consume_whitespace();
+    #[test]
+    fn to_query_string() {
+        let value = from_str(r#"{"a": 1, "b": "x y", "c": "\u00e9&=", "d": null, "e": true}"#).unwrap();
+        assert_eq!(value.to_query_string(), Ok("a=1&b=x%20y&c=%C3%A9%26%3D&d=&e=true".to_string()));
 
-                // The separating colon between key and value
-                if !self.ch_is(':') {
-                    return self.error(ExpectedColon);
-                }
-                self.consume_whitespace();
-                self.consume_char();
+        let nested = from_str(r#"{"a": [1]}"#).unwrap();
+        assert_eq!(nested.to_query_string().map_err(|e| e.reason), Err(TypeMismatch));
+        assert_eq!(Num(1.0).to_query_string().map_err(|e| e.reason), Err(TypeMismatch));
+    }
 
-                // Parse any value
-                let value = self.parse_value();
-                match value {
-                    Ok(v) => object.insert(key_string, v),
-                    e @ Err(_) => return e
-                };
-                self.consume_whitespace();
+    #[test]
+    fn from_json_tuples_and_arrays() {
+        let value = from_str(r#"[1.5, "x"]"#).unwrap();
+        assert_eq!(<(f64, String)>::from_json(&value), Ok((1.5, "x".to_string())));
+        assert_eq!(<(f64, String, bool)>::from_json(&value).map_err(|e| e.reason), Err(LengthMismatch));
+        assert_eq!(<(f64, f64)>::from_json(&value).map_err(|e| e.reason), Err(TypeMismatch));
 
-                // Continue with the next value
-                if self.ch_is(',') {
-                    self.consume_char();
-                    continue;
-                }
-                // End of the current object
-                if self.ch_is('}') {
-                    self.consume_char();
-                    return Ok(Object(object));
-                }
-            }
+        let value = from_str("[1, 2, 3]").unwrap();
+        assert_eq!(<[u32; 3]>::from_json(&value), Ok([1, 2, 3]));
+        assert_eq!(<[u32; 2]>::from_json(&value).map_err(|e| e.reason), Err(LengthMismatch));
+    }
+
+    #[test]
+    fn memory_size() {
+        assert_eq!(Num(1.0).memory_size(), 0);
+        assert!(Str("abc".to_string()).memory_size() >= 3);
+
+        let mut previous = 0;
+        for n in [1, 10, 100].iter() {
+            let values = (0..*n).map(|i| ObjectBuilder::new().key("id", Str(i.to_string())).build()).collect();
+            let size = Array(values).memory_size();
+            assert!(size > previous);
+            previous = size;
         }
-        
-        else {
-            self.error(UnclosedObject)
+    }
+
+    #[test]
+    fn parse_implicit_null() {
+        let input = r#"{"a":,"b":1, "c": }"#;
+        let options = ParserOptions { allow_implicit_null: true, ..ParserOptions::default() };
+        let value = JsonParser::with_options(input.chars(), options).parse().unwrap();
+        assert_eq!(value, from_str(r#"{"a": null, "b": 1, "c": null}"#).unwrap());
+        assert!(from_str(input).is_err());
+    }
+
+    #[test]
+    fn parse_with_scratch_buffer() {
+        let mut scratch = String::new();
+        for i in 0..100 {
+            let input = format!(r#"{{"name": "item {}", "tags": ["a\n", "{}"]}}"#, i, "x".repeat(i));
+            let value = JsonParser::with_scratch(input.chars(), ParserOptions::default(), &mut scratch)
+                .parse().unwrap();
+            assert_eq!(value, from_str(&input).unwrap());
+            assert_eq!(value["tags"][1].as_str().unwrap().len(), i);
         }
-        
+        assert!(scratch.capacity() >= 99);
+        let capacity = scratch.capacity();
+
+        let mut parser = JsonParser::with_scratch("\"abc".chars(), ParserOptions::default(), &mut scratch);
+        assert_eq!(parser.parse().map_err(|e| e.reason), Err(UnclosedStringLiteral));
+        assert_eq!(scratch.capacity(), capacity);
+        assert_eq!(scratch, "abc");
     }
 
-    pub fn parse(&mut self) -> JsonResult {
-        self.parse_value()
+    #[test]
+    fn dedup_array() {
+        let mut value = from_str("[1, 2, 1, 3, 2]").unwrap();
+        value.dedup_array();
+        assert_eq!(value, from_str("[1, 2, 3]").unwrap());
+
+        let mut value = from_str(r#"[{"a": [1]}, "x", {"a": [1]}, "x", null]"#).unwrap();
+        value.dedup_array();
+        assert_eq!(value, from_str(r#"[{"a": [1]}, "x", null]"#).unwrap());
+
+        let mut value = Num(1.0);
+        value.dedup_array();
+        assert_eq!(value, Num(1.0));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use super::JsonValue::*;
-    use super::ErrorCode::*;
-    use std::collections::HashMap;
-    use std::fs::File;
-    use std::io::prelude::*;
-    use std::io::BufReader;
-    use std::path::Path;
-    //use test::*;
+    #[test]
+    fn apply_merge_patch() {
+        let mut value = from_str(r#"{"title": "Goodbye!", "author": {"givenName": "John", "familyName": "Doe"},
+                                     "tags": ["example", "sample"], "content": "text"}"#).unwrap();
+        let patch = from_str(r#"{"title": "Hello!", "phoneNumber": "+01-123-456-7890",
+                                 "author": {"familyName": null, "address": {"city": "X"}}, "tags": ["example"]}"#).unwrap();
+        value.apply_merge_patch(&patch);
+        assert_eq!(value, from_str(r#"{"title": "Hello!", "author": {"givenName": "John", "address": {"city": "X"}},
+                                       "tags": ["example"], "content": "text", "phoneNumber": "+01-123-456-7890"}"#).unwrap());
+
+        let mut value = from_str("[1, 2]").unwrap();
+        value.apply_merge_patch(&from_str(r#"{"a": {"b": null}}"#).unwrap());
+        assert_eq!(value, ObjectBuilder::new().key("a", Object(Map::new())).build());
+
+        value.apply_merge_patch(&Str("replaced".to_string()));
+        assert_eq!(value, Str("replaced".to_string()));
+    }
 
     #[test]
-    fn parse_null() {
-        let mut parser = JsonParser::new("   null  ".chars());
-        let result = parser.parse_null();
-        assert_eq!(result, Ok(Null));
+    fn rename_keys() {
+        let mut value = from_str(r#"{"name": "x", "inner": {"list": [{"id": 1}], "a": true}}"#).unwrap();
+        value.rename_keys(|k| k.to_uppercase());
+        assert_eq!(value, from_str(r#"{"NAME": "x", "INNER": {"LIST": [{"ID": 1}], "A": true}}"#).unwrap());
+
+        let mut value = from_str(r#"{"a": 1, "A": 2, "b": 3}"#).unwrap();
+        value.rename_keys(|k| k.to_lowercase());
+        assert_eq!(value, from_str(r#"{"a": 1, "b": 3}"#).unwrap());
     }
 
     #[test]
-    fn parse_number() {
-        let mut parser = JsonParser::new("  4.2342 ".chars());
+    fn prune() {
+        let mut value = from_str(r#"{"a":null,"b":{},"c":1}"#).unwrap();
+        value.prune(true, true);
+        assert_eq!(value, from_str(r#"{"c":1}"#).unwrap());
 
-        let result = parser.parse_num();
-        assert_eq!(result, Ok(Num(4.2342)));
+        let input = r#"{"a": null, "b": {"x": null}, "c": [[], null]}"#;
+        let mut value = from_str(input).unwrap();
+        value.prune(true, true);
+        assert_eq!(value, from_str(r#"{"c": [[], null]}"#).unwrap());
+
+        let mut value = from_str(input).unwrap();
+        value.prune(false, true);
+        assert_eq!(value, from_str(input).unwrap());
     }
 
     #[test]
-    fn parse_number_2() {
-        let mut parser = JsonParser::new("  16237  ".chars());
-        let result = parser.parse_num();
-        assert_eq!(result, Ok(Num(16237.0)));
+    fn parse_empty_containers() {
+        let parse = |s: &str| JsonParser::new(s.chars()).parse();
+        assert_eq!(parse("[]"), Ok(Array(Vec::new())));
+        assert_eq!(parse("[ ]"), Ok(Array(Vec::new())));
+        assert!(matches!(parse("{}"), Ok(Object(ref map)) if map.is_empty()));
+        assert!(matches!(parse("{\n}"), Ok(Object(ref map)) if map.is_empty()));
+        assert_eq!(parse(r#"[[ ], {"a": {}}]"#), parse(r#"[[],{"a":{}}]"#));
+        assert!(parse(r#"[[ ], {"a": {}}]"#).is_ok());
+        assert_eq!(from_str("[]"), Ok(Array(Vec::new())));
+        assert!(matches!(from_str("{}"), Ok(Object(ref map)) if map.is_empty()));
     }
 
     #[test]
-    fn parse_number_error() {
-        let mut parser = JsonParser::new("  abcdef  ".chars());
-        let result = parser.parse_num();
-        match result {
-            Ok(_) => assert!(false),
-            Err(e) => assert_eq!(e.reason, NumberParsing)
+    fn parse_nan_infinity() {
+        let options = ParserOptions { allow_nan_infinity: true, ..ParserOptions::default() };
+        let parse = |input: &str| JsonParser::with_options(input.chars(), options.clone()).parse();
+        assert_eq!(parse("Infinity"), Ok(Num(f64::INFINITY)));
+        assert_eq!(parse("-Infinity"), Ok(Num(f64::NEG_INFINITY)));
+        assert!(parse("NaN").unwrap().as_f64().unwrap().is_nan());
+        assert_eq!(parse("[Infinity, -1, null]").unwrap()[0], Num(f64::INFINITY));
+        assert!(parse("Inf").is_err());
+        assert!(parse("NaNa").is_err());
+
+        for input in ["Infinity", "-Infinity", "NaN"].iter() {
+            assert!(from_str(input).is_err(), "{}", input);
         }
+
+        let options = ParserOptions { case_insensitive_literals: true, ..options };
+        assert_eq!(JsonParser::with_options("NULL".chars(), options).parse(), Ok(Null));
     }
 
     #[test]
-    fn parse_string() {
-        let mut parser = JsonParser::new("  \"String\" ".chars());
-        let result = parser.parse_string();
-        assert_eq!(result, Ok(Str("String".to_string())));
+    fn to_columns() {
+        let value = from_str(r#"[{"a":1},{"a":2,"b":3}]"#).unwrap();
+        let columns = value.to_columns().unwrap();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns["a"], vec![Num(1.0), Num(2.0)]);
+        assert_eq!(columns["b"], vec![Null, Num(3.0)]);
+
+        assert_eq!(from_str("[]").unwrap().to_columns(), Ok(HashMap::new()));
+        assert_eq!(from_str(r#"[{"a":1}, 2]"#).unwrap().to_columns().map_err(|e| e.reason), Err(TypeMismatch));
     }
 
     #[test]
-    fn parse_string_error() {
-        let mut parser = JsonParser::new("\"String".chars());
-        let result = parser.parse_string();
-        match result {
-            Ok(_) => assert!(false),
-            Err(err) => assert_eq!(err.reason, UnclosedStringLiteral)
-        }
-        
+    fn from_columns() {
+        let mut cols = HashMap::new();
+        cols.insert("a".to_string(), vec![Num(1.0), Num(2.0)]);
+        cols.insert("b".to_string(), vec![Null, Str("x".to_string())]);
+        let rows = JsonValue::from_columns(cols.clone()).unwrap();
+        assert_eq!(rows, from_str(r#"[{"a": 1, "b": null}, {"a": 2, "b": "x"}]"#).unwrap());
+        assert_eq!(rows.to_columns(), Ok(cols.clone()));
+
+        cols.insert("c".to_string(), vec![Bool(true)]);
+        assert_eq!(JsonValue::from_columns(cols).map_err(|e| e.reason), Err(LengthMismatch));
+        assert_eq!(JsonValue::from_columns(HashMap::new()), Ok(Array(Vec::new())));
     }
 
     #[test]
-    fn parse_bool() {
-        let mut parser = JsonParser::new("false".chars());
-        let result = parser.parse_bool();
-        assert_eq!(result, Ok(Bool(false)));
+    fn get_or() {
+        let value = from_str(r#"{"port": 8080}"#).unwrap();
+        let default = Num(80.0);
+        assert_eq!(value.get_or("port", &default), &Num(8080.0));
+        assert_eq!(value.get_or("host", &default), &default);
+        assert_eq!(Num(1.0).get_or("port", &default), &default);
+    }
 
-        parser = JsonParser::new("true".chars());
-        let result = parser.parse_bool();
-        assert_eq!(result, Ok(Bool(true)));
+    #[test]
+    fn coerce_numeric_strings() {
+        let mut value = from_str(r#"{"a":"42","b":"hello","c":["-1.5e3", " 7", "0x10", "01", "1."]}"#).unwrap();
+        value.coerce_numeric_strings();
+        assert_eq!(value, from_str(r#"{"a":42,"b":"hello","c":[-1500, " 7", "0x10", "01", "1."]}"#).unwrap());
     }
 
     #[test]
-    fn parse_bool_array() {
-        let mut parser = JsonParser::new("[ true , true , true ]".chars());
-        let result = parser.parse_array();
-        match result {
-            Ok(val) => {
-                let expected = Array(vec![Bool(true), Bool(true), Bool(true)]);
-                assert_eq!(val, expected);
-            }
-            Err(why) => {
-                panic!("{:?}", why);
-            }
-        }
+    fn parse_lenient() {
+        let (value, errors) = super::parse_lenient("[1, tru, 3,\n {\"a\" 1}, 5]");
+        assert_eq!(value, Some(from_str("[1, 3, 5]").unwrap()));
+        let errors: Vec<_> = errors.into_iter().map(|e| (e.reason, e.line)).collect();
+        assert_eq!(errors, vec![(ExpectedBool, 1), (ExpectedColon, 2)]);
+
+        let (value, errors) = super::parse_lenient("[1, 2");
+        assert_eq!(value, Some(from_str("[1, 2]").unwrap()));
+        assert_eq!(errors.len(), 1);
+
+        assert_eq!(super::parse_lenient("[true]"), (Some(Array(vec![Bool(true)])), Vec::new()));
+        assert_eq!(super::parse_lenient("").0, None);
+        let (value, errors) = super::parse_lenient("null x");
+        assert_eq!(value, Some(Null));
+        assert_eq!(errors[0].reason, TrailingCharacters);
     }
 
     #[test]
-    fn parse_num_array() {
-        let mut parser = JsonParser::new("[1.2, 4.2, 1.2, 4.5]".chars());
-        let result = parser.parse_array();
-        match result {
-            Ok(value) => {
-                let expected = Array(vec![Num(1.2), Num(4.2), Num(1.2), Num(4.5)]);
-                assert_eq!(expected, value);
-            }
-            Err(err) => {
-                panic!("{:?}", err);
-            }
-        }
+    fn from_json_int_bounds() {
+        let two_pow_63 = 9223372036854775808.0;
+        let two_pow_64 = 18446744073709551616.0;
+        assert_eq!(i64::from_json(&Num(two_pow_63)).map_err(|e| e.reason), Err(NumberOutOfRange));
+        assert_eq!(i64::from_json(&Num(-two_pow_63)), Ok(i64::MIN));
+        assert_eq!(i64::from_json(&U64(i64::MAX as u64)), Ok(i64::MAX));
+        assert_eq!(i64::from_json(&U64(i64::MAX as u64 + 1)).map_err(|e| e.reason), Err(NumberOutOfRange));
+        assert_eq!(u64::from_json(&Num(two_pow_64)).map_err(|e| e.reason), Err(NumberOutOfRange));
+        assert_eq!(u64::from_json(&U64(u64::MAX)), Ok(u64::MAX));
+        assert_eq!(u64::from_json(&Num(-1.0)).map_err(|e| e.reason), Err(NumberOutOfRange));
+        assert_eq!(i32::from_json(&Num(2147483647.0)), Ok(i32::MAX));
+        assert_eq!(i32::from_json(&Num(2147483648.0)).map_err(|e| e.reason), Err(NumberOutOfRange));
+        assert_eq!(u32::from_json(&Num(4294967295.0)), Ok(u32::MAX));
+        assert_eq!(i64::from_json(&Num(2.5)).map_err(|e| e.reason), Err(TypeMismatch));
+        assert_eq!(i64::from_json(&Str("5".to_string())).map_err(|e| e.reason), Err(TypeMismatch));
+
+        // Raw and decimal numbers convert like the others.
+        let options = ParserOptions { raw_numbers: true, ..ParserOptions::default() };
+        let raw = JsonParser::with_options("[5, -7, 1e2, 2.5, 18446744073709551615, 1e30]".chars(), options)
+            .parse().unwrap();
+        assert_eq!(i64::from_json(&raw[0]), Ok(5));
+        assert_eq!(i32::from_json(&raw[1]), Ok(-7));
+        assert_eq!(u32::from_json(&raw[2]), Ok(100));
+        assert_eq!(i64::from_json(&raw[3]).map_err(|e| e.reason), Err(TypeMismatch));
+        assert_eq!(u64::from_json(&raw[4]), Ok(u64::MAX));
+        assert_eq!(i64::from_json(&raw[4]).map_err(|e| e.reason), Err(NumberOutOfRange));
+        assert_eq!(u64::from_json(&raw[5]).map_err(|e| e.reason), Err(NumberOutOfRange));
+
+        let options = ParserOptions { decimal_numbers: true, ..ParserOptions::default() };
+        let dec = JsonParser::with_options("[5, -7.00, 0.25, 99999999999999999999]".chars(), options)
+            .parse().unwrap();
+        assert_eq!(i64::from_json(&dec[0]), Ok(5));
+        assert_eq!(i32::from_json(&dec[1]), Ok(-7));
+        assert_eq!(u64::from_json(&dec[1]).map_err(|e| e.reason), Err(NumberOutOfRange));
+        assert_eq!(i64::from_json(&dec[2]).map_err(|e| e.reason), Err(TypeMismatch));
+        assert_eq!(u64::from_json(&dec[3]).map_err(|e| e.reason), Err(NumberOutOfRange));
     }
 
     #[test]
-    fn parse_nested_array() {
-        let mut parser = JsonParser::new("[[true, true], [true, false]]".chars());
-        let result = parser.parse_value();
-        match result {
-            Ok(value) => {
-                let expected = Array(vec![
-                    Array(vec![Bool(true), Bool(true)]),
-                    Array(vec![Bool(true), Bool(false)])]);
-                assert_eq!(expected, value);
-            }
-            Err(err) => {
-                panic!("{:?}", err);
-            }
+    fn parse_array_recovery_in_string() {
+        let options = ParserOptions { recover_array_errors: true, ..ParserOptions::default() };
+        let mut parser = JsonParser::with_options(r#"[{"a": "x\q"}, 2]"#.chars(), options.clone());
+        assert_eq!(parser.parse(), Ok(Array(vec![Num(2.0)])));
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(parser.errors()[0].reason, InvalidEscape);
+
+        let mut parser = JsonParser::with_options(r#"["a\qb\"c", "d"]"#.chars(), options);
+        assert_eq!(parser.parse(), Ok(Array(vec![Str("d".to_string())])));
+    }
+
+    #[test]
+    fn parse_raw_numbers_grammar() {
+        let options = ParserOptions { raw_numbers: true, ..ParserOptions::default() };
+        for input in ["01", "1.", "-.5", "1.e5", "1e", "-"].iter() {
+            let result = JsonParser::with_options(input.chars(), options.clone()).parse();
+            assert_eq!(result.map_err(|e| e.reason), Err(NumberParsing), "{}", input);
         }
+        let result = JsonParser::with_options("-0.5e+3".chars(), options).parse();
+        assert_eq!(result, Ok(RawNumber("-0.5e+3".to_string())));
     }
 
     #[test]
-    fn parse_object_simple() {
-        let mut parser = JsonParser::new("{\"label\" : 1.5}".chars());
-        let result = parser.parse_object();
+    fn decimal_extreme_exponents() {
+        assert_eq!(Decimal::parse("1e-4294967297"), None);
+        assert_eq!(Decimal::parse("1e-39"), None);
+        assert_eq!(Decimal::parse("1e-38").map(|d| d.to_string()), Some("0.00000000000000000000000000000000000001".to_string()));
+        assert_eq!(Decimal::parse("1e9999999999"), None);
+        assert_eq!(Decimal::parse("1e-9223372036854775808"), None);
+        assert_eq!(Decimal::parse("1000e-40").map(|d| d.to_string()), Some(format!("0.{}1", "0".repeat(36))));
 
-        let mut obj = HashMap::new();
-        obj.insert("label".to_string(), Num(1.5));
+        let zero = Decimal::parse("0").unwrap();
+        assert_eq!(Decimal::parse("0e-9999999999"), Some(zero));
+        assert_eq!(Decimal::parse("-0.000e9999999999"), Some(zero));
+    }
 
-        assert_eq!(Object(obj), result.unwrap());
+    #[test]
+    fn normalize_decimal_numbers() {
+        let options = ParserOptions { decimal_numbers: true, ..ParserOptions::default() };
+        let mut value = JsonParser::with_options("[2, 2.50, 18446744073709551615]".chars(), options).parse().unwrap();
+        assert_eq!(value[0], Dec(Decimal::parse("2").unwrap()));
+        value.normalize_numbers();
+        assert_eq!(value, Array(vec![Num(2.0), Num(2.5), U64(u64::MAX)]));
     }
 
     #[test]
-    fn parse_object_array() {
-        let mut parser = JsonParser::new("{\"label\" : [true, true, true]}".chars());
-        let result = parser.parse_object();
+    fn parse_missing_commas() {
+        for input in ["[1 2]", r#"{"a":1 "b":2}"#, "[[1] [2]]", r#"[{"a": 1} 2]"#].iter() {
+            assert_eq!(from_str(input).map_err(|e| e.reason), Err(UnexpectedCharacter), "{}", input);
+            assert_eq!(Arena::new().parse(input).map(|_| ()).map_err(|e| e.reason), Err(UnexpectedCharacter), "{}", input);
+        }
+        let err = from_str("[1 2]").unwrap_err();
+        assert_eq!((err.line, err.col), (1, 4));
+    }
 
-        let mut obj = HashMap::new();
-        obj.insert("label".to_string(), Array(vec![Bool(true), Bool(true), Bool(true)]));
+    #[test]
+    fn push_parser_errors() {
+        let mut parser = PushParser::new();
+        parser.feed("[]").unwrap();
+        assert!(parser.is_complete());
+        assert_eq!(parser.feed(" [").unwrap_err().reason, TrailingCharacters);
+        assert!(!parser.is_complete());
 
-        assert_eq!(Object(obj), result.unwrap());
+        let mut parser = PushParser::new();
+        parser.feed("[1, 2").unwrap();
+        let err = parser.feed(" 3]").unwrap_err();
+        assert_eq!((err.reason.clone(), err.line, err.col), (UnexpectedCharacter, 1, 7));
+        assert_eq!(parser.feed("]").unwrap_err(), err);
 
+        let mut parser = PushParser::new();
+        parser.feed("{\"a\":\n tr").unwrap();
+        let err = parser.feed("ue, \"b\" 1}").unwrap_err();
+        assert_eq!((err.reason, err.line), (ExpectedColon, 2));
+
+        for input in ["[tru]", "{\"a\": 1]", "[\"\\q\"]", "]", "{1: 2}", "[1,]"].iter() {
+            let mut parser = PushParser::new();
+            assert!(parser.feed(input).is_err(), "{}", input);
+        }
+
+        let mut parser = PushParser::new();
+        parser.feed("4").unwrap();
+        assert!(!parser.is_complete());
+        parser.feed("2 ").unwrap();
+        assert!(parser.is_complete());
+        assert_eq!(parser.finish(), Ok(Num(42.0)));
+
+        // Errors match the regular parser's, which also skips a
+        // byte order mark.
+        let mut parser = PushParser::new();
+        parser.feed("\u{feff}").unwrap();
+        parser.feed("[1]").unwrap();
+        assert!(parser.is_complete());
+        assert_eq!(parser.finish(), Ok(Array(vec![Num(1.0)])));
+        for input in ["[1 2]", "{\"a\" 1}", "[1] x", "[\"a\\q\"]", "\n  {\"a\": tx}"].iter() {
+            let mut parser = PushParser::new();
+            for c in input.chars() {
+                let _ = parser.feed(&c.to_string());
+            }
+            assert_eq!(parser.feed(" ").unwrap_err(), from_str(input).unwrap_err(), "{}", input);
+        }
     }
-    
+
     #[test]
-    fn index_array() {
-    	let mut parser = JsonParser::new("[1, 2, 3, 4, 5]".chars());
-    	let result = parser.parse().unwrap();
-    	for i in 1..6 {
-    		assert_eq!(result[i-1], Num(i as f64));
-    	}
+    fn round_floats_overflow() {
+        let value = Array(vec![Num(1e307), Num(1.5), Num(-2.25)]);
+        assert_eq!(value.round_floats(2), value);
+        assert_eq!(value.round_floats(400), value);
+        assert_eq!(value.round_floats(u32::MAX), value);
+        assert_eq!(print_json(&value.round_floats(400)), print_json(&value));
     }
-    
+
     #[test]
-    fn index_object() {
-    	let mut parser = JsonParser::new("{\"label\" : 1.5}".chars());
-        let result = parser.parse_object().unwrap();
-        let indexed = result["label"].clone();
-        let expected = Num(1.5);
-        assert_eq!(indexed, expected);
+    fn parse_malformed_comments() {
+        let options = ParserOptions { allow_comments: true, ..ParserOptions::default() };
+        let parse = |input: &str| JsonParser::with_options(input.chars(), options.clone()).parse()
+            .map_err(|e| (e.reason, e.line, e.col));
+        assert_eq!(parse("[/1]"), Err((UnexpectedCharacter, 1, 2)));
+        assert_eq!(parse("1 /"), Err((UnexpectedCharacter, 1, 3)));
+        assert_eq!(parse("1 /* never closed"), Err((UnclosedComment, 1, 3)));
+        assert_eq!(parse("[1,\n /* a */ 2 /* b"), Err((UnclosedComment, 2, 12)));
+        assert_eq!(parse("[1 /* a */, 2] // done"), Ok(from_str("[1, 2]").unwrap()));
+
+        let mut parser = JsonParser::with_options("/* x 1".chars(), options.clone());
+        assert_eq!(parser.parse_partial().map_err(|e| e.reason), Err(UnclosedComment));
+        let mut parser = JsonParser::with_options("[1, /".chars(), options);
+        assert_eq!(parser.parse_keeping_partial().map_err(|(v, e)| (v, e.reason)),
+                   Err((Some(Array(vec![Num(1.0)])), UnexpectedCharacter)));
     }
-    
+
+    #[test]
+    fn nan_infinity_round_trip() {
+        let parser_options = ParserOptions { allow_nan_infinity: true, ..ParserOptions::default() };
+        let input = "[Infinity,NaN,-Infinity,1.5]";
+        let value = JsonParser::with_options(input.chars(), parser_options.clone()).parse().unwrap();
+
+        assert_eq!(value.to_string(), "[null,null,null,1.5]");
+        assert_eq!(value.serialized_len(), value.to_string().len());
+        assert_eq!(from_str(&value.to_string()).unwrap()[0], Null);
+        assert_eq!(format!("{:#}", value).matches("null").count(), 3);
+
+        let options = SerializerOptions { allow_nan_infinity: true, ..SerializerOptions::default() };
+        let output = to_string_with_options(&value, &options);
+        assert_eq!(output, input);
+        let reparsed = JsonParser::with_options(output.chars(), parser_options).parse().unwrap();
+        assert_eq!(reparsed[0], Num(f64::INFINITY));
+        assert!(reparsed[1].as_f64().unwrap().is_nan());
+        assert_eq!(reparsed[2], Num(f64::NEG_INFINITY));
+
+        let options = SerializerOptions { serde_json_compat: true, ..options };
+        assert_eq!(to_string_with_options(&value, &options), input);
+    }
+
     // fn big_json(count: usize) -> String {
     //     let mut src = "[\n".to_string();
     //     for _ in 0..count {
@@ -670,14 +5870,11 @@ mod tests {
 #[cfg(not(test))]
 fn main() {
     let args: Vec<String> = args().skip(1).collect();
-    let path = args[0].clone();
-    let mut file = File::open(path).unwrap();
-    let mut data = String::new();
-    file.read_to_string(&mut data).unwrap();
-
-    let mut parser = JsonParser::new(data.chars());
-    let result = parser.parse().unwrap();
-    println!("{}", result);
+    let stdout = io::stdout();
+    if let Err(message) = run(&args, io::stdin(), &mut stdout.lock()) {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
 
     // let start = time::precise_time_ns();
 